@@ -0,0 +1,45 @@
+use anyhow::{anyhow, Context, Result};
+use solana_cli_config::{Config, CONFIG_FILE};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::signature::{read_keypair_file, Keypair};
+
+/// RPC client and signer for a run, read from a Solana CLI config file and overridden by
+/// command-line flags.
+pub struct Settings {
+    pub rpc: RpcClient,
+    keypair_path: String,
+}
+
+impl Settings {
+    pub fn load(
+        config_file: Option<&str>,
+        url: Option<String>,
+        keypair: Option<String>,
+    ) -> Result<Self> {
+        let config = match config_file.or(CONFIG_FILE.as_deref()) {
+            Some(path) if std::path::Path::new(path).exists() => {
+                Config::load(path).with_context(|| format!("failed reading config file {path}"))?
+            }
+            Some(path) if config_file.is_some() => {
+                return Err(anyhow!("config file {path} not found"))
+            }
+            _ => Config::default(),
+        };
+
+        Ok(Settings {
+            rpc: RpcClient::new_with_commitment(
+                url.unwrap_or(config.json_rpc_url),
+                CommitmentConfig::confirmed(),
+            ),
+            keypair_path: keypair.unwrap_or(config.keypair_path),
+        })
+    }
+
+    /// Reads the keypair only once a command has to sign with it.
+    pub fn signer(&self) -> Result<Keypair> {
+        let path = shellexpand::tilde(&self.keypair_path);
+        read_keypair_file(&*path)
+            .map_err(|error| anyhow!("failed reading keypair {}: {error}", self.keypair_path))
+    }
+}
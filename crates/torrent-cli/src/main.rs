@@ -0,0 +1,457 @@
+//! Command-line tool for operating torrents.
+//!
+//! Reads the RPC URL and keypair from the Solana CLI config, which `--url` and
+//! `--keypair` override. Token amounts are in base units.
+
+mod config;
+mod output;
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_spl::associated_token::get_associated_token_address_with_program_id;
+use anchor_spl::token::ID as TOKEN_PROGRAM_ID;
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::transaction::Transaction;
+use spl_associated_token_account::instruction::create_associated_token_account_idempotent;
+use torrent::SwapDirection;
+use torrent_client::accounts::{fetch_pool_state, fetch_torrent, PoolState};
+use torrent_client::instructions::{self, SwapOptions, UserWallets};
+use torrent_client::{pda, quote};
+
+use config::Settings;
+use output::{print, OutputFormat, PoolView, TorrentView};
+
+#[derive(Parser)]
+#[command(
+    name = "torrent-cli",
+    version,
+    about = "Operate torrents and their pools"
+)]
+struct Cli {
+    /// Solana CLI config file to read the RPC URL and keypair from
+    #[arg(long, global = true)]
+    config: Option<String>,
+
+    /// RPC URL, overriding the config file
+    #[arg(long, short = 'u', global = true)]
+    url: Option<String>,
+
+    /// Keypair file to sign with, overriding the config file
+    #[arg(long, short = 'k', global = true)]
+    keypair: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, global = true)]
+    output: OutputFormat,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a torrent owned by the keypair
+    InitTorrent {
+        /// Decimals of the torrent's liquidity token
+        #[arg(long, default_value_t = 6)]
+        decimals: u8,
+    },
+
+    /// Create a pool seeded with an initial deposit from the keypair's token accounts
+    CreatePool {
+        torrent: Pubkey,
+        mint_x: Pubkey,
+        mint_y: Pubkey,
+        #[arg(long)]
+        initial_x: u64,
+        #[arg(long)]
+        initial_y: u64,
+        /// Fee tier, in basis points
+        #[arg(long)]
+        fee_tier: u16,
+    },
+
+    /// Deposit X and the matching amount of Y
+    AddLiquidity {
+        pool: Pubkey,
+        #[arg(long)]
+        amount_x: u64,
+    },
+
+    /// Burn liquidity tokens for both sides of a pool
+    RemoveLiquidity {
+        pool: Pubkey,
+        /// Liquidity tokens to burn
+        #[arg(long)]
+        amount: u64,
+    },
+
+    /// Swap an exact input amount
+    Swap {
+        pool: Pubkey,
+        #[arg(long)]
+        amount_in: u64,
+        #[arg(long, value_enum)]
+        direction: Direction,
+        /// Least output to accept. Defaults to the quote less the slippage tolerance
+        #[arg(long)]
+        min_amount_out: Option<u64>,
+        /// Slippage tolerance on the quote, in basis points
+        #[arg(long, default_value_t = 50)]
+        slippage_bps: u16,
+    },
+
+    /// Show a torrent's configuration
+    ShowTorrent { torrent: Pubkey },
+
+    /// Show a pool's state and reserves
+    ShowPool { pool: Pubkey },
+
+    /// Show every pool in a torrent
+    ListPools { torrent: Pubkey },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Direction {
+    XToY,
+    YToX,
+}
+
+impl From<Direction> for SwapDirection {
+    fn from(direction: Direction) -> Self {
+        match direction {
+            Direction::XToY => SwapDirection::XToY,
+            Direction::YToX => SwapDirection::YToX,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct InitTorrentResult {
+    signature: String,
+    torrent: String,
+    liquidity_token_mint: String,
+}
+
+#[derive(Serialize)]
+struct CreatePoolResult {
+    signature: String,
+    pool: String,
+}
+
+#[derive(Serialize)]
+struct AddLiquidityResult {
+    signature: String,
+    // Quoted before sending
+    amount_y: u64,
+    liquidity: u64,
+}
+
+#[derive(Serialize)]
+struct RemoveLiquidityResult {
+    signature: String,
+    // Quoted before sending
+    amount_x: u64,
+    amount_y: u64,
+}
+
+#[derive(Serialize)]
+struct SwapResult {
+    signature: String,
+    // Quoted before sending
+    amount_out: u64,
+    fee: u64,
+    min_amount_out: u64,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let settings = Settings::load(cli.config.as_deref(), cli.url, cli.keypair)?;
+    let rpc = &settings.rpc;
+
+    match cli.command {
+        Command::InitTorrent { decimals } => {
+            let signer = settings.signer()?;
+            let (torrent, _) = pda::find_torrent_address(&signer.pubkey());
+
+            let signature = send(
+                &settings,
+                &signer,
+                vec![instructions::create_torrent(&signer.pubkey(), decimals)],
+            )?;
+
+            print(
+                cli.output,
+                &InitTorrentResult {
+                    signature,
+                    torrent: torrent.to_string(),
+                    liquidity_token_mint: pda::find_liquidity_token_mint_address(&torrent)
+                        .0
+                        .to_string(),
+                },
+            )
+        }
+
+        Command::CreatePool {
+            torrent,
+            mint_x,
+            mint_y,
+            initial_x,
+            initial_y,
+            fee_tier,
+        } => {
+            let signer = settings.signer()?;
+            let torrent_state = fetch_torrent(rpc, &torrent)?;
+
+            let pool_creation = &torrent_state.pool_creation;
+            if pool_creation.fee > 0
+                && pool_creation.fee_mint != Pubkey::default()
+                && torrent_state.roles.pool_creator != signer.pubkey()
+            {
+                bail!("pool creation fees paid in tokens aren't supported");
+            }
+
+            let token_x_program = rpc.get_account(&mint_x)?.owner;
+            let token_y_program = rpc.get_account(&mint_y)?.owner;
+            let liquidity_token_mint = torrent_state.liquidity_token_mint;
+            let creator = UserWallets {
+                user: signer.pubkey(),
+                x_wallet: associated(&signer, &mint_x, &token_x_program),
+                y_wallet: associated(&signer, &mint_y, &token_y_program),
+                liquidity_token_wallet: associated(
+                    &signer,
+                    &liquidity_token_mint,
+                    &TOKEN_PROGRAM_ID,
+                ),
+                native_sol: false,
+            };
+
+            let signature = send(
+                &settings,
+                &signer,
+                vec![
+                    create_wallet(&signer, &liquidity_token_mint, &TOKEN_PROGRAM_ID),
+                    instructions::create_pool(
+                        &torrent,
+                        &pool_creation.treasury,
+                        &creator,
+                        &mint_x,
+                        &mint_y,
+                        &token_x_program,
+                        &token_y_program,
+                        initial_x,
+                        initial_y,
+                        fee_tier,
+                    ),
+                ],
+            )?;
+
+            print(
+                cli.output,
+                &CreatePoolResult {
+                    signature,
+                    pool: pda::find_pool_address(&torrent, &mint_x, &mint_y, fee_tier)
+                        .0
+                        .to_string(),
+                },
+            )
+        }
+
+        Command::AddLiquidity { pool, amount_x } => {
+            let signer = settings.signer()?;
+            let state = fetch_pool_state(rpc, &pool)?;
+            let quote = quote::quote_add_liquidity(&state, amount_x)?;
+            let (user, liquidity_token_mint) = user_wallets(&signer, &state);
+
+            let signature = send(
+                &settings,
+                &signer,
+                vec![
+                    create_wallet(&signer, &liquidity_token_mint, &TOKEN_PROGRAM_ID),
+                    instructions::deposit(&state, &user, amount_x, None),
+                ],
+            )?;
+
+            print(
+                cli.output,
+                &AddLiquidityResult {
+                    signature,
+                    amount_y: quote.amount_y,
+                    liquidity: quote.liquidity,
+                },
+            )
+        }
+
+        Command::RemoveLiquidity { pool, amount } => {
+            let signer = settings.signer()?;
+            let state = fetch_pool_state(rpc, &pool)?;
+            let quote = quote::quote_remove_liquidity(&state, amount)?;
+            let (user, _) = user_wallets(&signer, &state);
+
+            let signature = send(
+                &settings,
+                &signer,
+                [
+                    create_output_wallet(&signer, &state.mint_x, &state.token_x_program),
+                    create_output_wallet(&signer, &state.mint_y, &state.token_y_program),
+                    Some(instructions::withdraw(&state, &user, amount, None)),
+                ]
+                .into_iter()
+                .flatten()
+                .collect(),
+            )?;
+
+            print(
+                cli.output,
+                &RemoveLiquidityResult {
+                    signature,
+                    amount_x: quote.amount_x,
+                    amount_y: quote.amount_y,
+                },
+            )
+        }
+
+        Command::Swap {
+            pool,
+            amount_in,
+            direction,
+            min_amount_out,
+            slippage_bps,
+        } => {
+            let signer = settings.signer()?;
+            let state = fetch_pool_state(rpc, &pool)?;
+            let direction = SwapDirection::from(direction);
+            let quote = quote::quote_swap(&state, amount_in, direction, rpc.get_slot()?)?;
+            let min_amount_out = match min_amount_out {
+                Some(min_amount_out) => min_amount_out,
+                None => torrent_math::mul_div(
+                    quote.amount_out,
+                    torrent_math::BPS_DENOMINATOR.saturating_sub(slippage_bps as u64),
+                    torrent_math::BPS_DENOMINATOR,
+                    torrent_math::Rounding::Down,
+                )
+                .map_err(torrent_client::ClientError::from)?,
+            };
+
+            let (mint_out, token_out_program) = match direction {
+                SwapDirection::XToY => (state.mint_y, state.token_y_program),
+                SwapDirection::YToX => (state.mint_x, state.token_x_program),
+            };
+            // Volume discounts apply once the trader has opened their stats in the pool
+            let (trader_stats, _) =
+                pda::find_trader_stats_address(&state.address, &signer.pubkey());
+            let options = SwapOptions {
+                trader_stats: rpc.get_account(&trader_stats).is_ok(),
+                ..SwapOptions::default()
+            };
+            let (user, _) = user_wallets(&signer, &state);
+
+            let signature = send(
+                &settings,
+                &signer,
+                [
+                    create_output_wallet(&signer, &mint_out, &token_out_program),
+                    Some(instructions::swap_exact_in(
+                        &state,
+                        &user,
+                        amount_in,
+                        min_amount_out,
+                        direction,
+                        options,
+                    )),
+                ]
+                .into_iter()
+                .flatten()
+                .collect(),
+            )?;
+
+            print(
+                cli.output,
+                &SwapResult {
+                    signature,
+                    amount_out: quote.amount_out,
+                    fee: quote.fee,
+                    min_amount_out,
+                },
+            )
+        }
+
+        Command::ShowTorrent { torrent } => print(
+            cli.output,
+            &TorrentView::new(&torrent, &fetch_torrent(rpc, &torrent)?),
+        ),
+
+        Command::ShowPool { pool } => print(
+            cli.output,
+            &PoolView::new(&fetch_pool_state(rpc, &pool)?, rpc.get_slot()?),
+        ),
+
+        Command::ListPools { torrent } => {
+            let torrent_state = fetch_torrent(rpc, &torrent)?;
+            let slot = rpc.get_slot()?;
+
+            let pools = torrent_state
+                .pools
+                .iter()
+                .filter(|pool| **pool != Pubkey::default())
+                .map(|pool| Ok(PoolView::new(&fetch_pool_state(rpc, pool)?, slot)))
+                .collect::<Result<Vec<_>>>()?;
+
+            print(cli.output, &pools)
+        }
+    }
+}
+
+fn send(settings: &Settings, signer: &Keypair, instructions: Vec<Instruction>) -> Result<String> {
+    let transaction = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&signer.pubkey()),
+        &[signer],
+        settings.rpc.get_latest_blockhash()?,
+    );
+
+    Ok(settings
+        .rpc
+        .send_and_confirm_transaction(&transaction)?
+        .to_string())
+}
+
+fn associated(signer: &Keypair, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    get_associated_token_address_with_program_id(&signer.pubkey(), mint, token_program)
+}
+
+/// Creates the signer's associated token account for `mint` unless it already exists.
+fn create_wallet(signer: &Keypair, mint: &Pubkey, token_program: &Pubkey) -> Instruction {
+    create_associated_token_account_idempotent(
+        &signer.pubkey(),
+        &signer.pubkey(),
+        mint,
+        token_program,
+    )
+}
+
+/// Creates the signer's wallet for a token paid out by the pool. Native SOL is paid
+/// straight to the signer.
+fn create_output_wallet(
+    signer: &Keypair,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Option<Instruction> {
+    (!torrent::is_native_mint(mint)).then(|| create_wallet(signer, mint, token_program))
+}
+
+/// The signer's associated token accounts for a pool, paying native SOL sides in SOL, and
+/// the torrent's liquidity token mint.
+fn user_wallets(signer: &Keypair, state: &PoolState) -> (UserWallets, Pubkey) {
+    let (liquidity_token_mint, _) = pda::find_liquidity_token_mint_address(&state.pool.torrent);
+
+    (
+        UserWallets {
+            native_sol: true,
+            ..UserWallets::associated(&signer.pubkey(), state, &liquidity_token_mint)
+        },
+        liquidity_token_mint,
+    )
+}
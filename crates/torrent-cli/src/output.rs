@@ -0,0 +1,201 @@
+use anchor_lang::prelude::Pubkey;
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::Value;
+use torrent::Torrent;
+use torrent_client::accounts::PoolState;
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// Prints a command's result. Text output lists the same fields as JSON, one per line.
+pub fn print(format: OutputFormat, value: &impl Serialize) -> Result<()> {
+    let value = serde_json::to_value(value)?;
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&value)?),
+        OutputFormat::Text => {
+            let mut text = String::new();
+            write_text(&value, 0, &mut text);
+            print!("{text}");
+        }
+    }
+
+    Ok(())
+}
+
+fn write_text(value: &Value, indent: usize, text: &mut String) {
+    let pad = " ".repeat(indent);
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields {
+                if is_nested(field) {
+                    text.push_str(&format!("{pad}{name}:\n"));
+                    write_text(field, indent + 2, text);
+                } else {
+                    text.push_str(&format!("{pad}{name}: {}\n", scalar(field)));
+                }
+            }
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                if item.is_object() {
+                    // Separate records with a blank line
+                    if i > 0 {
+                        text.push('\n');
+                    }
+                    write_text(item, indent, text);
+                } else {
+                    text.push_str(&format!("{pad}- {}\n", scalar(item)));
+                }
+            }
+        }
+        _ => text.push_str(&format!("{pad}{}\n", scalar(value))),
+    }
+}
+
+fn is_nested(value: &Value) -> bool {
+    match value {
+        Value::Object(fields) => !fields.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        _ => false,
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        Value::Null => "none".to_string(),
+        Value::Array(_) => "[]".to_string(),
+        _ => value.to_string(),
+    }
+}
+
+#[derive(Serialize)]
+pub struct RolesView {
+    pub admin: String,
+    pub pool_creator: String,
+    pub fee_manager: String,
+    pub pauser: String,
+    pub gatekeeper: String,
+}
+
+#[derive(Serialize)]
+pub struct TorrentView {
+    pub address: String,
+    pub authority: String,
+    pub roles: RolesView,
+    pub liquidity_token_mint: String,
+    pub torrent_liquidity: u64,
+    pub paused: bool,
+    pub permissionless_pool_creation: bool,
+    pub pool_creation_fee: u64,
+    pub fee_tiers: Vec<u16>,
+    pub timelock_delay: i64,
+    pub pools: Vec<String>,
+}
+
+impl TorrentView {
+    pub fn new(address: &Pubkey, torrent: &Torrent) -> Self {
+        let roles = &torrent.roles;
+
+        TorrentView {
+            address: address.to_string(),
+            authority: torrent.authority.to_string(),
+            roles: RolesView {
+                admin: roles.admin.to_string(),
+                pool_creator: roles.pool_creator.to_string(),
+                fee_manager: roles.fee_manager.to_string(),
+                pauser: roles.pauser.to_string(),
+                gatekeeper: roles.gatekeeper.to_string(),
+            },
+            liquidity_token_mint: torrent.liquidity_token_mint.to_string(),
+            torrent_liquidity: torrent.torrent_liquidity,
+            paused: torrent.paused,
+            permissionless_pool_creation: torrent.pool_creation.permissionless,
+            pool_creation_fee: torrent.pool_creation.fee,
+            fee_tiers: torrent
+                .fee_tiers
+                .iter()
+                .copied()
+                .filter(|tier| *tier != 0)
+                .collect(),
+            timelock_delay: torrent.timelock_delay,
+            pools: torrent
+                .pools
+                .iter()
+                .filter(|pool| **pool != Pubkey::default())
+                .map(ToString::to_string)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PoolView {
+    pub address: String,
+    pub torrent: String,
+    pub index: u8,
+    pub mint_x: String,
+    pub mint_y: String,
+    pub reserve_x: u64,
+    pub reserve_y: u64,
+    pub pool_liquidity: u64,
+    pub fee_tier: u16,
+    // Swap fee at the slot the pool was read
+    pub current_fee_bps: u16,
+    pub dynamic_fee: bool,
+    pub delisted: bool,
+    pub allow_list: bool,
+}
+
+impl PoolView {
+    pub fn new(state: &PoolState, slot: u64) -> Self {
+        let pool = &state.pool;
+
+        PoolView {
+            address: state.address.to_string(),
+            torrent: pool.torrent.to_string(),
+            index: pool.index,
+            mint_x: state.mint_x.to_string(),
+            mint_y: state.mint_y.to_string(),
+            reserve_x: state.reserve_x,
+            reserve_y: state.reserve_y,
+            pool_liquidity: pool.pool_liquidity,
+            fee_tier: pool.fee_tier,
+            current_fee_bps: pool.current_fee_bps(slot),
+            dynamic_fee: pool.dynamic_fee.enabled,
+            delisted: pool.delisted,
+            allow_list: pool.allow_list,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn text_output_lists_fields_in_order() {
+        let mut text = String::new();
+        write_text(
+            &json!({
+                "address": "pool",
+                "fee_tiers": [5, 30],
+                "roles": { "admin": "key" },
+                "pools": [],
+            }),
+            0,
+            &mut text,
+        );
+
+        assert_eq!(
+            text,
+            "address: pool\nfee_tiers:\n  - 5\n  - 30\nroles:\n  admin: key\npools: []\n"
+        );
+    }
+}
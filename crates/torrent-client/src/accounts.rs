@@ -0,0 +1,102 @@
+//! Fetching and decoding torrent accounts.
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::AccountDeserialize;
+use anchor_spl::token_2022::spl_token_2022::{
+    extension::StateWithExtensions, state::Account as TokenAccount,
+};
+use solana_client::rpc_client::RpcClient;
+use torrent::{Pool, Torrent};
+
+use crate::{pda, ClientError, Result};
+
+/// Decodes an anchor account, checking its discriminator.
+pub fn decode<T: AccountDeserialize>(mut data: &[u8]) -> Result<T> {
+    Ok(T::try_deserialize(&mut data)?)
+}
+
+pub fn fetch<T: AccountDeserialize>(rpc: &RpcClient, address: &Pubkey) -> Result<T> {
+    decode(&rpc.get_account_data(address)?)
+}
+
+pub fn fetch_torrent(rpc: &RpcClient, address: &Pubkey) -> Result<Torrent> {
+    fetch(rpc, address)
+}
+
+pub fn fetch_pool(rpc: &RpcClient, address: &Pubkey) -> Result<Pool> {
+    fetch(rpc, address)
+}
+
+/// Every pool registered in `torrent`, by address.
+pub fn fetch_pools(rpc: &RpcClient, torrent: &Torrent) -> Result<Vec<(Pubkey, Pool)>> {
+    let addresses: Vec<Pubkey> = torrent
+        .pools
+        .iter()
+        .copied()
+        .filter(|pool| *pool != Pubkey::default())
+        .collect();
+
+    rpc.get_multiple_accounts(&addresses)?
+        .into_iter()
+        .zip(addresses)
+        .map(|(account, address)| match account {
+            Some(account) => Ok((address, decode(&account.data)?)),
+            None => Err(ClientError::AccountNotFound(address)),
+        })
+        .collect()
+}
+
+/// A pool along with its token pair and reserves, which the pool account doesn't store.
+/// Enough to quote it and build instructions against it.
+#[derive(Clone)]
+pub struct PoolState {
+    pub address: Pubkey,
+    pub pool: Pool,
+
+    pub mint_x: Pubkey,
+    pub mint_y: Pubkey,
+    pub x_token_vault: Pubkey,
+    pub y_token_vault: Pubkey,
+    pub token_x_program: Pubkey,
+    pub token_y_program: Pubkey,
+
+    // Vault balances
+    pub reserve_x: u64,
+    pub reserve_y: u64,
+}
+
+pub fn fetch_pool_state(rpc: &RpcClient, address: &Pubkey) -> Result<PoolState> {
+    let (x_token_vault, _) = pda::find_x_vault_address(address);
+    let (y_token_vault, _) = pda::find_y_vault_address(address);
+
+    let mut accounts = rpc
+        .get_multiple_accounts(&[*address, x_token_vault, y_token_vault])?
+        .into_iter();
+    let mut next = |address: Pubkey| {
+        accounts
+            .next()
+            .flatten()
+            .ok_or(ClientError::AccountNotFound(address))
+    };
+
+    let pool = next(*address)?;
+    let x_vault = next(x_token_vault)?;
+    let y_vault = next(y_token_vault)?;
+
+    let x_balance = StateWithExtensions::<TokenAccount>::unpack(&x_vault.data)?.base;
+    let y_balance = StateWithExtensions::<TokenAccount>::unpack(&y_vault.data)?.base;
+
+    Ok(PoolState {
+        address: *address,
+        pool: decode(&pool.data)?,
+        mint_x: x_balance.mint,
+        mint_y: y_balance.mint,
+        x_token_vault,
+        y_token_vault,
+        // Vaults are owned by their mint's token program
+        token_x_program: x_vault.owner,
+        token_y_program: y_vault.owner,
+        reserve_x: x_balance.amount,
+        reserve_y: y_balance.amount,
+    })
+}
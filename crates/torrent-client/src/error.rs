@@ -0,0 +1,29 @@
+use anchor_lang::prelude::{ProgramError, Pubkey};
+use thiserror::Error;
+use torrent_math::MathError;
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error(transparent)]
+    Rpc(#[from] solana_client::client_error::ClientError),
+
+    #[error(transparent)]
+    Anchor(#[from] anchor_lang::error::Error),
+
+    #[error(transparent)]
+    Program(#[from] ProgramError),
+
+    #[error("account {0} not found")]
+    AccountNotFound(Pubkey),
+
+    #[error("quote failed: {0:?}")]
+    Math(MathError),
+}
+
+impl From<MathError> for ClientError {
+    fn from(error: MathError) -> Self {
+        ClientError::Math(error)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ClientError>;
@@ -0,0 +1,487 @@
+//! Instruction builders.
+//!
+//! Every program instruction has a builder taking its accounts and arguments as the
+//! program's own generated types, so a builder can't go stale when an instruction
+//! changes. The core pool flows also have builders deriving their accounts from a
+//! `PoolState`.
+
+use anchor_lang::prelude::{AccountMeta, Pubkey};
+use anchor_lang::solana_program::{instruction::Instruction, system_program, sysvar};
+use anchor_lang::{InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::get_associated_token_address_with_program_id;
+use anchor_spl::token::ID as TOKEN_PROGRAM_ID;
+use torrent::{accounts, instruction, PoolSide, SwapDirection};
+
+use crate::accounts::PoolState;
+use crate::pda;
+
+fn build(accounts: impl ToAccountMetas, args: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: torrent::ID,
+        accounts: accounts.to_account_metas(None),
+        data: args.data(),
+    }
+}
+
+macro_rules! builders {
+    ($($name:ident($accounts:ident, $args:ident);)*) => {
+        $(
+            pub fn $name(accounts: accounts::$accounts, args: instruction::$args) -> Instruction {
+                build(accounts, args)
+            }
+        )*
+    };
+}
+
+builders! {
+    initialize_torrent(InitializeTorrent, InitializeTorrent);
+    set_role(UpdateTorrent, SetRole);
+    revoke_role(UpdateTorrent, RevokeRole);
+    set_multisig(UpdateTorrent, SetMultisig);
+    set_paused(SetPaused, SetPaused);
+    add_fee_tier(ManageFeeTiers, AddFeeTier);
+    remove_fee_tier(ManageFeeTiers, RemoveFeeTier);
+    queue_change(QueueChange, QueueChange);
+    execute_change(ExecuteChange, ExecuteChange);
+    cancel_change(CancelChange, CancelChange);
+    initialize_pool(InitializePool, InitializePool);
+    delist_pool(DelistPool, DelistPool);
+    set_allow_list(UpdatePool, SetAllowList);
+    issue_allowance(IssueAllowance, IssueAllowance);
+    revoke_allowance(RevokeAllowance, RevokeAllowance);
+    set_deposit_caps(UpdatePool, SetDepositCaps);
+    add_liquidity(AlterLiquidity, AddLiquidity);
+    add_liquidity_single(AlterLiquidity, AddLiquiditySingle);
+    remove_liquidity(AlterLiquidity, RemoveLiquidity);
+    remove_liquidity_single(AlterLiquidity, RemoveLiquiditySingle);
+    open_position(OpenPosition, OpenPosition);
+    add_position_liquidity(AlterPositionLiquidity, AddPositionLiquidity);
+    remove_position_liquidity(AlterPositionLiquidity, RemovePositionLiquidity);
+    swap(Swap, Swap);
+    quote_swap(QuotePool, QuoteSwap);
+    quote_add_liquidity(QuotePool, QuoteAddLiquidity);
+    quote_remove_liquidity(QuotePool, QuoteRemoveLiquidity);
+    register_referrer(RegisterReferrer, RegisterReferrer);
+    open_trader_stats(OpenTraderStats, OpenTraderStats);
+    create_farm(CreateFarm, CreateFarm);
+    add_reward_stream(AddRewardStream, AddRewardStream);
+    stake_lp(AlterStake, StakeLp);
+    unstake_lp(AlterStake, UnstakeLp);
+    claim_rewards(ClaimRewards, ClaimRewards);
+}
+
+/// Reward accounts for one stream of a `claim_all_rewards`, in stream order
+pub struct RewardAccounts {
+    pub reward_mint: Pubkey,
+    pub user_reward_wallet: Pubkey,
+    pub reward_token_program: Pubkey,
+}
+
+pub fn claim_all_rewards(
+    accounts: accounts::ClaimAllRewards,
+    streams: &[RewardAccounts],
+) -> Instruction {
+    let farm = accounts.farm;
+    let mut ix = build(accounts, instruction::ClaimAllRewards);
+
+    for stream in streams {
+        let (reward_vault, _) = pda::find_farm_reward_vault_address(&farm, &stream.reward_mint);
+        ix.accounts.extend([
+            AccountMeta::new_readonly(stream.reward_mint, false),
+            AccountMeta::new(reward_vault, false),
+            AccountMeta::new(stream.user_reward_wallet, false),
+            AccountMeta::new_readonly(stream.reward_token_program, false),
+        ]);
+    }
+
+    ix
+}
+
+/// Adds multisig co-signers to an instruction the multisig has to approve.
+pub fn with_signers(mut ix: Instruction, signers: &[Pubkey]) -> Instruction {
+    ix.accounts.extend(
+        signers
+            .iter()
+            .map(|signer| AccountMeta::new_readonly(*signer, true)),
+    );
+    ix
+}
+
+/// Creates the torrent owned by `authority`, along with its liquidity token mint.
+pub fn create_torrent(authority: &Pubkey, decimals: u8) -> Instruction {
+    let (torrent, _) = pda::find_torrent_address(authority);
+
+    initialize_torrent(
+        accounts::InitializeTorrent {
+            authority: *authority,
+            torrent,
+            liquidity_token: pda::find_liquidity_token_mint_address(&torrent).0,
+            system_program: system_program::ID,
+            token_program: TOKEN_PROGRAM_ID,
+            rent: sysvar::rent::ID,
+        },
+        instruction::InitializeTorrent {
+            _decimals: decimals,
+        },
+    )
+}
+
+/// Token accounts a user deposits from and withdraws to
+pub struct UserWallets {
+    pub user: Pubkey,
+    pub x_wallet: Pubkey,
+    pub y_wallet: Pubkey,
+    pub liquidity_token_wallet: Pubkey,
+
+    // Pay and be paid native mint sides in SOL, through the user's native wallet rather
+    // than the x or y wallet. Pool creation always uses the x and y wallets.
+    pub native_sol: bool,
+}
+
+impl UserWallets {
+    /// The user's associated token accounts for the pool's pair and liquidity token.
+    pub fn associated(user: &Pubkey, state: &PoolState, liquidity_token_mint: &Pubkey) -> Self {
+        let wallet =
+            |mint, program| get_associated_token_address_with_program_id(user, mint, program);
+
+        UserWallets {
+            user: *user,
+            x_wallet: wallet(&state.mint_x, &state.token_x_program),
+            y_wallet: wallet(&state.mint_y, &state.token_y_program),
+            liquidity_token_wallet: wallet(liquidity_token_mint, &TOKEN_PROGRAM_ID),
+            native_sol: false,
+        }
+    }
+
+    /// The x and y wallets passed for `state`, and the native wallet if a side is paid in
+    /// SOL instead.
+    fn for_pool(&self, state: &PoolState) -> (Option<Pubkey>, Option<Pubkey>, Option<Pubkey>) {
+        let in_sol = |mint| self.native_sol && torrent::is_native_mint(mint);
+        let x_wallet = (!in_sol(&state.mint_x)).then_some(self.x_wallet);
+        let y_wallet = (!in_sol(&state.mint_y)).then_some(self.y_wallet);
+        let native_wallet = (x_wallet.is_none() || y_wallet.is_none())
+            .then(|| pda::find_native_wallet_address(&state.address, &self.user).0);
+
+        (x_wallet, y_wallet, native_wallet)
+    }
+}
+
+/// Creates a pool in `torrent` seeded with the creator's initial deposit. Pools charging
+/// a creation fee in tokens need `initialize_pool` with the fee accounts instead.
+#[allow(clippy::too_many_arguments)]
+pub fn create_pool(
+    torrent: &Pubkey,
+    treasury: &Pubkey,
+    creator: &UserWallets,
+    mint_x: &Pubkey,
+    mint_y: &Pubkey,
+    token_x_program: &Pubkey,
+    token_y_program: &Pubkey,
+    initial_x: u64,
+    initial_y: u64,
+    fee_tier: u16,
+) -> Instruction {
+    let (pool, _) = pda::find_pool_address(torrent, mint_x, mint_y, fee_tier);
+
+    initialize_pool(
+        accounts::InitializePool {
+            torrent: *torrent,
+            liquidity_token_mint: pda::find_liquidity_token_mint_address(torrent).0,
+            creator: creator.user,
+            mint_x: *mint_x,
+            mint_y: *mint_y,
+            creator_x_wallet: creator.x_wallet,
+            creator_y_wallet: creator.y_wallet,
+            creator_liquidity_token_wallet: creator.liquidity_token_wallet,
+            pool,
+            x_token_vault: pda::find_x_vault_address(&pool).0,
+            y_token_vault: pda::find_y_vault_address(&pool).0,
+            treasury: *treasury,
+            fee_mint: None,
+            creator_fee_wallet: None,
+            treasury_fee_wallet: None,
+            fee_token_program: None,
+            system_program: system_program::ID,
+            token_program: TOKEN_PROGRAM_ID,
+            token_x_program: *token_x_program,
+            token_y_program: *token_y_program,
+            rent: sysvar::rent::ID,
+        },
+        instruction::InitializePool {
+            initial_x,
+            initial_y,
+            fee_tier,
+        },
+    )
+}
+
+fn alter_liquidity_accounts(
+    state: &PoolState,
+    user: &UserWallets,
+    allowance: bool,
+) -> accounts::AlterLiquidity {
+    let torrent = state.pool.torrent;
+    let (user_x_wallet, user_y_wallet, native_wallet) = user.for_pool(state);
+
+    accounts::AlterLiquidity {
+        user: user.user,
+        torrent,
+        pool: state.address,
+        allowance: allowance.then(|| pda::find_allowance_address(&torrent, &user.user).0),
+        x_token_vault: state.x_token_vault,
+        y_token_vault: state.y_token_vault,
+        mint_x: state.mint_x,
+        mint_y: state.mint_y,
+        liquidity_token_mint: pda::find_liquidity_token_mint_address(&torrent).0,
+        user_x_wallet,
+        user_y_wallet,
+        native_wallet,
+        user_liquidity_token_wallet: user.liquidity_token_wallet,
+        system_program: system_program::ID,
+        token_program: TOKEN_PROGRAM_ID,
+        token_x_program: state.token_x_program,
+        token_y_program: state.token_y_program,
+    }
+}
+
+/// Deposits `amount_x` and the matching amount of Y. Passes the user's allowance when
+/// the pool is allow-listed.
+pub fn deposit(
+    state: &PoolState,
+    user: &UserWallets,
+    amount_x: u64,
+    deadline: Option<i64>,
+) -> Instruction {
+    add_liquidity(
+        alter_liquidity_accounts(state, user, state.pool.allow_list),
+        instruction::AddLiquidity {
+            amount_x,
+            _amount_y: 0,
+            deadline,
+        },
+    )
+}
+
+pub fn deposit_single(
+    state: &PoolState,
+    user: &UserWallets,
+    amount_in: u64,
+    min_lp_out: u64,
+    side: PoolSide,
+    deadline: Option<i64>,
+) -> Instruction {
+    add_liquidity_single(
+        alter_liquidity_accounts(state, user, state.pool.allow_list),
+        instruction::AddLiquiditySingle {
+            amount_in,
+            min_lp_out,
+            side,
+            deadline,
+        },
+    )
+}
+
+/// Burns `lt_amount` liquidity tokens for both sides of the pool.
+pub fn withdraw(
+    state: &PoolState,
+    user: &UserWallets,
+    lt_amount: u64,
+    deadline: Option<i64>,
+) -> Instruction {
+    remove_liquidity(
+        alter_liquidity_accounts(state, user, false),
+        instruction::RemoveLiquidity {
+            lt_amount,
+            deadline,
+        },
+    )
+}
+
+/// Burns `lt_amount` liquidity tokens for one side of the pool. Like a single-sided deposit
+/// this swaps against the pool, so it passes the user's allowance when the pool is
+/// allow-listed.
+pub fn withdraw_single(
+    state: &PoolState,
+    user: &UserWallets,
+    lt_amount: u64,
+    min_amount_out: u64,
+    side: PoolSide,
+    deadline: Option<i64>,
+) -> Instruction {
+    remove_liquidity_single(
+        alter_liquidity_accounts(state, user, state.pool.allow_list),
+        instruction::RemoveLiquiditySingle {
+            lt_amount,
+            min_amount_out,
+            side,
+            deadline,
+        },
+    )
+}
+
+/// Optional accounts a swap can be made with
+#[derive(Default)]
+pub struct SwapOptions {
+    // Referrer account and its wallet for the input token
+    pub referrer: Option<(Pubkey, Pubkey)>,
+
+    // Whether the trader has opened trader stats in the pool for volume discounts
+    pub trader_stats: bool,
+
+    pub deadline: Option<i64>,
+}
+
+pub fn swap_exact_in(
+    state: &PoolState,
+    user: &UserWallets,
+    amount_in: u64,
+    min_amount_out: u64,
+    direction: SwapDirection,
+    options: SwapOptions,
+) -> Instruction {
+    let torrent = state.pool.torrent;
+    let (user_x_wallet, user_y_wallet, native_wallet) = user.for_pool(state);
+
+    swap(
+        accounts::Swap {
+            user: user.user,
+            torrent,
+            pool: state.address,
+            allowance: state
+                .pool
+                .allow_list
+                .then(|| pda::find_allowance_address(&torrent, &user.user).0),
+            x_token_vault: state.x_token_vault,
+            y_token_vault: state.y_token_vault,
+            mint_x: state.mint_x,
+            mint_y: state.mint_y,
+            user_x_wallet,
+            user_y_wallet,
+            native_wallet,
+            referrer: options.referrer.map(|(referrer, _)| referrer),
+            referrer_wallet: options.referrer.map(|(_, wallet)| wallet),
+            trader_stats: options
+                .trader_stats
+                .then(|| pda::find_trader_stats_address(&state.address, &user.user).0),
+            system_program: system_program::ID,
+            token_x_program: state.token_x_program,
+            token_y_program: state.token_y_program,
+        },
+        instruction::Swap {
+            amount_in,
+            min_amount_out,
+            direction,
+            deadline: options.deadline,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::AnchorDeserialize;
+    use torrent::Pool;
+
+    fn pool_state(allow_list: bool) -> PoolState {
+        let mut pool = Pool::try_from_slice(&[0; Pool::SIZE]).unwrap();
+        pool.torrent = Pubkey::new_unique();
+        pool.allow_list = allow_list;
+
+        PoolState {
+            address: Pubkey::new_unique(),
+            pool,
+            mint_x: Pubkey::new_unique(),
+            mint_y: Pubkey::new_unique(),
+            x_token_vault: Pubkey::new_unique(),
+            y_token_vault: Pubkey::new_unique(),
+            token_x_program: TOKEN_PROGRAM_ID,
+            token_y_program: TOKEN_PROGRAM_ID,
+            reserve_x: 0,
+            reserve_y: 0,
+        }
+    }
+
+    fn user(state: &PoolState) -> UserWallets {
+        UserWallets::associated(&Pubkey::new_unique(), state, &Pubkey::new_unique())
+    }
+
+    #[test]
+    fn swap_leaves_unused_optional_accounts_empty() {
+        let state = pool_state(false);
+        let ix = swap_exact_in(
+            &state,
+            &user(&state),
+            100,
+            90,
+            SwapDirection::XToY,
+            SwapOptions::default(),
+        );
+
+        // Anchor marks a missing optional account with the program id
+        assert_eq!(ix.accounts[3].pubkey, torrent::ID);
+        assert_eq!(ix.accounts[10].pubkey, torrent::ID);
+        assert_eq!(ix.accounts[11].pubkey, torrent::ID);
+        assert_eq!(ix.accounts[12].pubkey, torrent::ID);
+        assert_eq!(ix.accounts[13].pubkey, torrent::ID);
+    }
+
+    #[test]
+    fn native_sol_sides_use_the_native_wallet() {
+        let mut state = pool_state(false);
+        state.mint_x = anchor_spl::token::spl_token::native_mint::ID;
+        let user = UserWallets {
+            native_sol: true,
+            ..user(&state)
+        };
+        let (native_wallet, _) = pda::find_native_wallet_address(&state.address, &user.user);
+
+        let ix = swap_exact_in(
+            &state,
+            &user,
+            100,
+            90,
+            SwapDirection::XToY,
+            SwapOptions::default(),
+        );
+        assert_eq!(ix.accounts[8].pubkey, torrent::ID);
+        assert_eq!(ix.accounts[9].pubkey, user.y_wallet);
+        assert_eq!(ix.accounts[10].pubkey, native_wallet);
+
+        // Without native SOL the user's wrapped SOL wallet is used as is
+        let user = UserWallets {
+            native_sol: false,
+            ..user
+        };
+        let ix = deposit(&state, &user, 100, None);
+        assert_eq!(ix.accounts[9].pubkey, user.x_wallet);
+        assert_eq!(ix.accounts[11].pubkey, torrent::ID);
+    }
+
+    #[test]
+    fn allow_listed_pools_get_the_users_allowance() {
+        let state = pool_state(true);
+        let user = user(&state);
+        let (allowance, _) = pda::find_allowance_address(&state.pool.torrent, &user.user);
+
+        let ix = deposit(&state, &user, 100, None);
+        assert_eq!(ix.accounts[3].pubkey, allowance);
+
+        // Single-sided withdrawals swap against the pool, proportional ones don't
+        let ix = withdraw_single(&state, &user, 100, 90, PoolSide::X, None);
+        assert_eq!(ix.accounts[3].pubkey, allowance);
+        let ix = withdraw(&state, &user, 100, None);
+        assert_eq!(ix.accounts[3].pubkey, torrent::ID);
+    }
+
+    #[test]
+    fn co_signers_are_appended_as_signers() {
+        let signers = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let ix = with_signers(create_torrent(&Pubkey::new_unique(), 6), &signers);
+
+        let appended = &ix.accounts[ix.accounts.len() - 2..];
+        assert!(appended
+            .iter()
+            .zip(signers)
+            .all(|(meta, signer)| meta.pubkey == signer && meta.is_signer && !meta.is_writable));
+    }
+}
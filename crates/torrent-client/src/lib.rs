@@ -0,0 +1,16 @@
+//! Rust client for the torrent program.
+//!
+//! Derives program addresses, builds instructions, fetches and decodes program accounts
+//! and quotes pool operations, using only `anchor-lang` and the Solana RPC client.
+
+#![allow(clippy::result_large_err)]
+
+pub mod accounts;
+pub mod instructions;
+pub mod quote;
+
+mod error;
+
+pub use error::{ClientError, Result};
+pub use torrent::pda;
+pub use torrent::ID as PROGRAM_ID;
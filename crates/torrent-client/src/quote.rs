@@ -0,0 +1,53 @@
+//! Off-chain quotes, computed the same way as the program's `quote_*` instructions.
+//!
+//! Token-2022 transfer fees aren't deducted here. For mints charging them, simulate the
+//! `quote_*` instructions instead.
+
+use torrent::{AddLiquidityQuote, RemoveLiquidityQuote, SwapDirection, SwapQuote};
+
+use crate::accounts::PoolState;
+use crate::Result;
+
+/// Swapping `amount_in` at `slot`, before any volume discount or referral fee.
+pub fn quote_swap(
+    state: &PoolState,
+    amount_in: u64,
+    direction: SwapDirection,
+    slot: u64,
+) -> Result<SwapQuote> {
+    let (reserve_in, reserve_out) = match direction {
+        SwapDirection::XToY => (state.reserve_x, state.reserve_y),
+        SwapDirection::YToX => (state.reserve_y, state.reserve_x),
+    };
+    let fee_bps = state.pool.current_fee_bps(slot);
+
+    Ok(SwapQuote {
+        amount_out: torrent_math::swap_output(amount_in, reserve_in, reserve_out, fee_bps)?,
+        fee: torrent_math::swap_fee(amount_in, fee_bps)?,
+        fee_bps,
+    })
+}
+
+pub fn quote_add_liquidity(state: &PoolState, amount_x: u64) -> Result<AddLiquidityQuote> {
+    let amount_y = torrent_math::deposit_amount(amount_x, state.reserve_x, state.reserve_y)?;
+
+    Ok(AddLiquidityQuote {
+        amount_y,
+        liquidity: torrent_math::lp_tokens_for_deposit(
+            amount_x,
+            amount_y,
+            state.reserve_x,
+            state.reserve_y,
+            state.pool.pool_liquidity,
+        )?,
+    })
+}
+
+pub fn quote_remove_liquidity(state: &PoolState, lt_amount: u64) -> Result<RemoveLiquidityQuote> {
+    let pool_liquidity = state.pool.pool_liquidity;
+
+    Ok(RemoveLiquidityQuote {
+        amount_x: torrent_math::withdraw_amount(lt_amount, state.reserve_x, pool_liquidity)?,
+        amount_y: torrent_math::withdraw_amount(lt_amount, state.reserve_y, pool_liquidity)?,
+    })
+}
@@ -0,0 +1,202 @@
+//! Constant product curve math shared by the torrent program and its clients.
+//!
+//! Every formula states which way it rounds. Amounts paid out of a pool round down and
+//! amounts paid into it round up, so rounding never moves value out of the pool.
+//! Intermediates are computed in 256 bits, so any u64 reserves and amounts are safe and
+//! only results that don't fit back in a u64 fail.
+#![cfg_attr(not(test), no_std)]
+
+pub mod u256;
+
+use u256::U256;
+
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    Down,
+    Up,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MathError {
+    /// A result doesn't fit in its type
+    Overflow,
+    /// A reserve, liquidity supply or other divisor was zero
+    DivisionByZero,
+    /// A fee above `BPS_DENOMINATOR`
+    InvalidFee,
+}
+
+pub type Result<T> = core::result::Result<T, MathError>;
+
+/// `a * b / c`, computed without overflowing the intermediate product.
+pub fn mul_div(a: u64, b: u64, c: u64, rounding: Rounding) -> Result<u64> {
+    u256::to_u64(u256::div(
+        U256::from(a) * U256::from(b),
+        U256::from(c),
+        rounding,
+    )?)
+}
+
+/// Basis points of an input left after a fee of `fee_bps`.
+fn fee_complement(fee_bps: u16) -> Result<U256> {
+    let fee_bps = fee_bps as u64;
+    if fee_bps > BPS_DENOMINATOR {
+        return Err(MathError::InvalidFee);
+    }
+
+    Ok(U256::from(BPS_DENOMINATOR - fee_bps))
+}
+
+/// What's left of `amount_in` after a swap fee of `fee_bps`, rounded down.
+pub fn amount_after_fee(amount_in: u64, fee_bps: u16) -> Result<u64> {
+    u256::to_u64(u256::div(
+        U256::from(amount_in) * fee_complement(fee_bps)?,
+        U256::from(BPS_DENOMINATOR),
+        Rounding::Down,
+    )?)
+}
+
+/// Fee taken from `amount_in` by a swap charging `fee_bps`. It's whatever
+/// `amount_after_fee` doesn't leave, so it rounds up.
+pub fn swap_fee(amount_in: u64, fee_bps: u16) -> Result<u64> {
+    Ok(amount_in - amount_after_fee(amount_in, fee_bps)?)
+}
+
+/// Output of trading `amount_in` against the reserves, net of the swap fee, rounded down.
+/// The fee is applied inside the fraction so it isn't rounded separately.
+pub fn swap_output(amount_in: u64, reserve_in: u64, reserve_out: u64, fee_bps: u16) -> Result<u64> {
+    let amount_in_after_fee = U256::from(amount_in) * fee_complement(fee_bps)?;
+    let numerator = U256::from(reserve_out) * amount_in_after_fee;
+    let denominator = U256::from(reserve_in) * U256::from(BPS_DENOMINATOR) + amount_in_after_fee;
+
+    // Never more than reserve_out, so it always fits
+    u256::to_u64(u256::div(numerator, denominator, Rounding::Down)?)
+}
+
+/// Amount of the other token to deposit alongside `amount`, keeping the reserve ratio.
+/// Rounded up.
+pub fn deposit_amount(amount: u64, reserve: u64, reserve_other: u64) -> Result<u64> {
+    mul_div(amount, reserve_other, reserve, Rounding::Up)
+}
+
+/// Liquidity minted for a deposit that adds `x_received` and `y_received` to the
+/// reserves. Each side is valued at its share of its reserve and the smaller share is
+/// minted, rounded down.
+pub fn lp_tokens_for_deposit(
+    x_received: u64,
+    y_received: u64,
+    reserve_x: u64,
+    reserve_y: u64,
+    pool_liquidity: u64,
+) -> Result<u64> {
+    let x_share = u256::div(
+        U256::from(x_received) * U256::from(pool_liquidity),
+        U256::from(reserve_x),
+        Rounding::Down,
+    )?;
+    let y_share = u256::div(
+        U256::from(y_received) * U256::from(pool_liquidity),
+        U256::from(reserve_y),
+        Rounding::Down,
+    )?;
+
+    u256::to_u64(x_share.min(y_share))
+}
+
+/// Liquidity minted for a pool's first deposit, the mean of the two amounts rounded down.
+pub fn initial_lp_tokens(x_received: u64, y_received: u64) -> Result<u64> {
+    u256::to_u64((U256::from(x_received) + U256::from(y_received)) / 2)
+}
+
+/// Share of `reserve` owed for burning `liquidity` out of `pool_liquidity`, rounded down.
+pub fn withdraw_amount(liquidity: u64, reserve: u64, pool_liquidity: u64) -> Result<u64> {
+    if liquidity > pool_liquidity {
+        return Err(MathError::Overflow);
+    }
+
+    mul_div(liquidity, reserve, pool_liquidity, Rounding::Down)
+}
+
+/// Portion of a single-sided deposit of `amount_in` to swap so the remainder and the
+/// swap output match the post-swap reserves. This is the positive root of
+/// `(1 - f) s^2 + (2 - f) R s - a R = 0`, scaled by `BPS_DENOMINATOR` and rounded down.
+pub fn single_sided_swap_amount(amount_in: u64, reserve_in: u64, fee_bps: u16) -> Result<u64> {
+    if fee_bps as u64 >= BPS_DENOMINATOR {
+        return Err(MathError::InvalidFee);
+    }
+
+    let denominator = U256::from(BPS_DENOMINATOR);
+    let fee = U256::from(fee_bps);
+    let reserve_in = U256::from(reserve_in);
+
+    let b = reserve_in * (denominator * 2 - fee);
+    let four_ac = U256::from(amount_in) * reserve_in * (denominator - fee) * denominator * 4;
+
+    let discriminant: U256 = b * b + four_ac;
+
+    u256::to_u64((discriminant.integer_sqrt() - b) / ((denominator - fee) * 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mul_div_rounds_in_the_requested_direction() {
+        assert_eq!(mul_div(10, 10, 3, Rounding::Down), Ok(33));
+        assert_eq!(mul_div(10, 10, 3, Rounding::Up), Ok(34));
+        assert_eq!(mul_div(10, 9, 3, Rounding::Up), Ok(30));
+        assert_eq!(
+            mul_div(1, 1, 0, Rounding::Down),
+            Err(MathError::DivisionByZero)
+        );
+        assert_eq!(
+            mul_div(u64::MAX, 2, 1, Rounding::Down),
+            Err(MathError::Overflow)
+        );
+    }
+
+    #[test]
+    fn swap_fee_and_remainder_add_up() {
+        for amount in [0, 1, 99, 10_001, u64::MAX] {
+            let after_fee = amount_after_fee(amount, 30).unwrap();
+            assert_eq!(after_fee + swap_fee(amount, 30).unwrap(), amount);
+        }
+        assert_eq!(swap_fee(1, 30), Ok(1));
+    }
+
+    #[test]
+    fn swap_output_never_lowers_k() {
+        let (reserve_in, reserve_out) = (1_000_000u64, 2_000_000u64);
+        let amount_in = 12_345;
+        let amount_out = swap_output(amount_in, reserve_in, reserve_out, 30).unwrap();
+
+        let k_before = reserve_in as u128 * reserve_out as u128;
+        let k_after = (reserve_in + amount_in) as u128 * (reserve_out - amount_out) as u128;
+        assert!(k_after >= k_before);
+    }
+
+    #[test]
+    fn deposits_round_in_the_pools_favour() {
+        assert_eq!(deposit_amount(1, 3, 2), Ok(1));
+        assert_eq!(lp_tokens_for_deposit(1, 1, 3, 2, 2), Ok(0));
+        assert_eq!(withdraw_amount(1, 2, 3), Ok(0));
+        assert_eq!(withdraw_amount(4, 2, 3), Err(MathError::Overflow));
+    }
+
+    #[test]
+    fn single_sided_deposit_leaves_the_pool_ratio() {
+        let (reserve_in, reserve_out) = (1_000_000u64, 4_000_000u64);
+        let amount_in = 100_000;
+
+        let swap_amount = single_sided_swap_amount(amount_in, reserve_in, 30).unwrap();
+        let swapped_out = swap_output(swap_amount, reserve_in, reserve_out, 30).unwrap();
+
+        // Remaining deposit over swap output matches the post-swap reserve ratio
+        let lhs = (amount_in - swap_amount) as u128 * (reserve_out - swapped_out) as u128;
+        let rhs = swapped_out as u128 * (reserve_in + swap_amount) as u128;
+        assert!(lhs.abs_diff(rhs) <= lhs / 1_000);
+    }
+}
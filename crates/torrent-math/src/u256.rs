@@ -0,0 +1,36 @@
+//! 256-bit intermediates for curve math. Products of two or three u64 amounts and a
+//! basis point scale fit comfortably, so formulas never overflow before their final
+//! narrowing back to u64.
+
+use crate::{MathError, Result, Rounding};
+
+#[allow(clippy::all)]
+mod construct {
+    uint::construct_uint! {
+        pub struct U256(4);
+    }
+}
+
+pub use construct::U256;
+
+/// `numerator / denominator` rounded in the given direction.
+pub fn div(numerator: U256, denominator: U256, rounding: Rounding) -> Result<U256> {
+    if denominator.is_zero() {
+        return Err(MathError::DivisionByZero);
+    }
+
+    let (quotient, remainder) = numerator.div_mod(denominator);
+    match rounding {
+        Rounding::Up if !remainder.is_zero() => Ok(quotient + 1),
+        _ => Ok(quotient),
+    }
+}
+
+/// Narrows a result back to u64.
+pub fn to_u64(value: U256) -> Result<u64> {
+    if value > U256::from(u64::MAX) {
+        return Err(MathError::Overflow);
+    }
+
+    Ok(value.low_u64())
+}
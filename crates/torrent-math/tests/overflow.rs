@@ -0,0 +1,91 @@
+//! Property tests: curve formulas don't overflow for any reserves up to `u64::MAX`, and
+//! keep the invariants the program relies on.
+
+use proptest::prelude::*;
+use torrent_math::u256::U256;
+use torrent_math::*;
+
+fn fee_bps() -> impl Strategy<Value = u16> {
+    0..BPS_DENOMINATOR as u16
+}
+
+proptest! {
+    #[test]
+    fn swap_output_fits_and_never_lowers_k(
+        amount_in in any::<u64>(),
+        reserve_in in 1..=u64::MAX,
+        reserve_out in any::<u64>(),
+        fee_bps in fee_bps(),
+    ) {
+        let amount_out = swap_output(amount_in, reserve_in, reserve_out, fee_bps).unwrap();
+        prop_assert!(amount_out <= reserve_out);
+
+        let k_before = U256::from(reserve_in) * U256::from(reserve_out);
+        let k_after = (U256::from(reserve_in) + U256::from(amount_in))
+            * U256::from(reserve_out - amount_out);
+        prop_assert!(k_after >= k_before);
+    }
+
+    #[test]
+    fn swap_fee_is_within_the_fee_rate(amount_in in any::<u64>(), fee_bps in fee_bps()) {
+        let fee = swap_fee(amount_in, fee_bps).unwrap();
+        let exact = amount_in as u128 * fee_bps as u128 / BPS_DENOMINATOR as u128;
+        prop_assert!(fee as u128 == exact || fee as u128 == exact + 1);
+    }
+
+    #[test]
+    fn deposit_amount_fits_within_the_other_reserve(
+        (reserve, amount) in (1..=u64::MAX).prop_flat_map(|reserve| (Just(reserve), 0..=reserve)),
+        reserve_other in any::<u64>(),
+    ) {
+        let paired = deposit_amount(amount, reserve, reserve_other).unwrap();
+        prop_assert!(paired <= reserve_other);
+    }
+
+    #[test]
+    fn lp_tokens_never_exceed_the_deposits_share(
+        (reserve_x, x_received) in (1..=u64::MAX).prop_flat_map(|r| (Just(r), 0..=r)),
+        (reserve_y, y_received) in (1..=u64::MAX).prop_flat_map(|r| (Just(r), 0..=r)),
+        pool_liquidity in any::<u64>(),
+    ) {
+        let minted =
+            lp_tokens_for_deposit(x_received, y_received, reserve_x, reserve_y, pool_liquidity)
+                .unwrap();
+        prop_assert!(minted <= pool_liquidity);
+        prop_assert!(
+            U256::from(minted) * U256::from(reserve_x)
+                <= U256::from(x_received) * U256::from(pool_liquidity)
+        );
+    }
+
+    #[test]
+    fn withdrawals_never_exceed_the_reserve(
+        (pool_liquidity, liquidity) in (1..=u64::MAX).prop_flat_map(|l| (Just(l), 0..=l)),
+        reserve in any::<u64>(),
+    ) {
+        let owed = withdraw_amount(liquidity, reserve, pool_liquidity).unwrap();
+        prop_assert!(owed <= reserve);
+    }
+
+    #[test]
+    fn single_sided_swap_amount_fits_in_the_deposit(
+        amount_in in any::<u64>(),
+        reserve_in in any::<u64>(),
+        fee_bps in fee_bps(),
+    ) {
+        let swap_amount = single_sided_swap_amount(amount_in, reserve_in, fee_bps).unwrap();
+        prop_assert!(swap_amount <= amount_in);
+    }
+
+    #[test]
+    fn rounding_up_adds_at_most_one(a in any::<u64>(), b in any::<u64>(), c in 1..=u64::MAX) {
+        let down = mul_div(a, b, c, Rounding::Down);
+        let up = mul_div(a, b, c, Rounding::Up);
+        match (down, up) {
+            (Ok(down), Ok(up)) => prop_assert!(up == down || up == down + 1),
+            (Ok(down), Err(MathError::Overflow)) => prop_assert_eq!(down, u64::MAX),
+            (Err(MathError::Overflow), Err(MathError::Overflow)) => {}
+            other => prop_assert!(false, "unexpected results {:?}", other),
+        }
+    }
+}
@@ -1,11 +1,42 @@
+#![allow(clippy::result_large_err)]
+
 use anchor_lang::prelude::*;
-use anchor_spl::token::{Burn, Mint, MintTo, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::program_pack::Pack;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token::spl_token::instruction::AuthorityType;
+use anchor_spl::token::{Burn, Mint, MintTo, SetAuthority, Token, TokenAccount};
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{
+        transfer_fee::TransferFeeConfig, BaseStateWithExtensions, ExtensionType,
+        StateWithExtensions,
+    },
+};
+use anchor_spl::token_interface::{
+    self, CloseAccount, InitializeAccount3, TokenInterface, TransferChecked,
+};
+use std::collections::BTreeMap;
 
 declare_id!("HzL5F7ePCv4bftNfSnMeGWtAYafJgGe5imiJjrD1Gd8n");
 
 pub const MAX_POOLS: usize = 10;
+pub const MAX_FEE_TIERS: usize = 8;
+pub const DEFAULT_FEE_TIERS: [u16; 4] = [1, 5, 30, 100];
+pub use torrent_math::BPS_DENOMINATOR;
+pub const MAX_VOLATILITY_ACCUMULATOR: u64 = 1_000_000;
+pub const DEFAULT_TIMELOCK_DELAY: i64 = SECONDS_PER_DAY;
+pub const DEFAULT_MAX_REFERRAL_SHARE_BPS: u16 = 2_000;
+pub const MAX_VOLUME_TIERS: usize = 4;
+pub const VOLUME_WINDOW_DAYS: usize = 30;
+pub const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+pub const MAX_MULTISIG_SIGNERS: usize = 10;
+pub const REWARD_PRECISION: u128 = 1_000_000_000_000;
+pub const MAX_REWARD_STREAMS: usize = 4;
+
+pub mod pda;
 
-mod torrent_test;
+#[cfg(test)]
+mod test;
 
 #[program]
 pub mod torrent {
@@ -14,10 +45,160 @@ pub mod torrent {
     pub fn initialize_torrent(ctx: Context<InitializeTorrent>, _decimals: u8) -> Result<()> {
         let torrent = &mut ctx.accounts.torrent;
         torrent.authority = ctx.accounts.authority.key();
+        torrent.roles = Roles::new(ctx.accounts.authority.key());
         torrent.liquidity_token_mint = ctx.accounts.liquidity_token.key();
         torrent.torrent_liquidity = 0;
         torrent.pools = [Pubkey::default(); MAX_POOLS];
         torrent.bump = *ctx.bumps.get("torrent").unwrap();
+        torrent.fee_tiers = [0; MAX_FEE_TIERS];
+        torrent.fee_tiers[..DEFAULT_FEE_TIERS.len()].copy_from_slice(&DEFAULT_FEE_TIERS);
+        torrent.mint_policy = MintPolicy::default();
+        torrent.pool_creation = PoolCreationConfig {
+            treasury: ctx.accounts.authority.key(),
+            ..PoolCreationConfig::default()
+        };
+        torrent.paused = false;
+        torrent.timelock_delay = DEFAULT_TIMELOCK_DELAY;
+        torrent.change_count = 0;
+        torrent.farm_count = 0;
+        torrent.max_referral_share_bps = DEFAULT_MAX_REFERRAL_SHARE_BPS;
+        torrent.volume_tiers = [VolumeTier::default(); MAX_VOLUME_TIERS];
+        torrent.volume_quote_mint = Pubkey::default();
+
+        Ok(())
+    }
+
+    pub fn set_role(ctx: Context<UpdateTorrent>, role: Role, key: Pubkey) -> Result<()> {
+        ctx.accounts
+            .torrent
+            .multisig
+            .check(ctx.remaining_accounts)?;
+        require!(
+            role != Role::Admin || key != Pubkey::default(),
+            CustomError::InvalidRoleAssignment
+        );
+
+        ctx.accounts.torrent.roles.set(role, key);
+
+        Ok(())
+    }
+
+    pub fn revoke_role(ctx: Context<UpdateTorrent>, role: Role) -> Result<()> {
+        ctx.accounts
+            .torrent
+            .multisig
+            .check(ctx.remaining_accounts)?;
+        require!(role != Role::Admin, CustomError::InvalidRoleAssignment);
+
+        ctx.accounts.torrent.roles.set(role, Pubkey::default());
+
+        Ok(())
+    }
+
+    pub fn set_multisig(
+        ctx: Context<UpdateTorrent>,
+        signers: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
+        ctx.accounts
+            .torrent
+            .multisig
+            .check(ctx.remaining_accounts)?;
+
+        ctx.accounts.torrent.multisig = Multisig::new(&signers, threshold)?;
+
+        Ok(())
+    }
+
+    pub fn set_paused(ctx: Context<SetPaused>, paused: bool) -> Result<()> {
+        ctx.accounts.torrent.paused = paused;
+
+        Ok(())
+    }
+
+    pub fn add_fee_tier(ctx: Context<ManageFeeTiers>, fee_bps: u16) -> Result<()> {
+        ctx.accounts
+            .torrent
+            .multisig
+            .check(ctx.remaining_accounts)?;
+        ctx.accounts.torrent.add_fee_tier(fee_bps)
+    }
+
+    pub fn remove_fee_tier(ctx: Context<ManageFeeTiers>, fee_bps: u16) -> Result<()> {
+        ctx.accounts
+            .torrent
+            .multisig
+            .check(ctx.remaining_accounts)?;
+        ctx.accounts.torrent.remove_fee_tier(fee_bps)
+    }
+
+    pub fn queue_change(ctx: Context<QueueChange>, change: ParameterChange) -> Result<()> {
+        ctx.accounts
+            .torrent
+            .multisig
+            .check(ctx.remaining_accounts)?;
+        change.validate()?;
+
+        let torrent = &mut ctx.accounts.torrent;
+        let eta = Clock::get()?
+            .unix_timestamp
+            .checked_add(torrent.timelock_delay)
+            .unwrap();
+
+        let pending_change = &mut ctx.accounts.pending_change;
+        pending_change.torrent = torrent.key();
+        pending_change.id = torrent.change_count;
+        pending_change.proposer = ctx.accounts.proposer.key();
+        pending_change.change = change;
+        pending_change.eta = eta;
+        pending_change.bump = *ctx.bumps.get("pending_change").unwrap();
+        torrent.change_count = torrent.change_count.checked_add(1).unwrap();
+
+        emit!(ChangeQueued {
+            torrent: torrent.key(),
+            id: pending_change.id,
+            change,
+            eta,
+        });
+
+        Ok(())
+    }
+
+    pub fn execute_change(ctx: Context<ExecuteChange>) -> Result<()> {
+        let pending_change = &ctx.accounts.pending_change;
+        require!(
+            Clock::get()?.unix_timestamp >= pending_change.eta,
+            CustomError::TimelockNotElapsed
+        );
+
+        let torrent = &mut ctx.accounts.torrent;
+        let pool = ctx
+            .accounts
+            .pool
+            .as_mut()
+            .map(|pool| (pool.key(), &mut ***pool));
+        pending_change
+            .change
+            .apply(torrent, pool, Clock::get()?.slot)?;
+
+        emit!(ChangeExecuted {
+            torrent: torrent.key(),
+            id: pending_change.id,
+        });
+
+        Ok(())
+    }
+
+    pub fn cancel_change(ctx: Context<CancelChange>) -> Result<()> {
+        ctx.accounts
+            .torrent
+            .multisig
+            .check(ctx.remaining_accounts)?;
+
+        emit!(ChangeCancelled {
+            torrent: ctx.accounts.torrent.key(),
+            id: ctx.accounts.pending_change.id,
+        });
 
         Ok(())
     }
@@ -26,27 +207,83 @@ pub mod torrent {
         ctx: Context<InitializePool>,
         initial_x: u64,
         initial_y: u64,
+        fee_tier: u16,
     ) -> Result<()> {
+        require!(
+            fee_tier != 0 && ctx.accounts.torrent.fee_tiers.contains(&fee_tier),
+            CustomError::UnknownFeeTier
+        );
+
+        let mint_policy = ctx.accounts.torrent.mint_policy;
+        mint_policy.check(&ctx.accounts.mint_x)?;
+        mint_policy.check(&ctx.accounts.mint_y)?;
+
+        let pool_creation = ctx.accounts.torrent.pool_creation;
+        if pool_creation.fee > 0
+            && ctx.accounts.creator.key() != ctx.accounts.torrent.roles.pool_creator
+        {
+            if pool_creation.fee_mint == Pubkey::default() {
+                anchor_lang::system_program::transfer(
+                    CpiContext::new(
+                        ctx.accounts.system_program.to_account_info(),
+                        anchor_lang::system_program::Transfer {
+                            from: ctx.accounts.creator.to_account_info(),
+                            to: ctx.accounts.treasury.to_account_info(),
+                        },
+                    ),
+                    pool_creation.fee,
+                )?;
+            } else {
+                let (fee_mint, creator_fee_wallet, treasury_fee_wallet, fee_token_program) = match (
+                    &ctx.accounts.fee_mint,
+                    &ctx.accounts.creator_fee_wallet,
+                    &ctx.accounts.treasury_fee_wallet,
+                    &ctx.accounts.fee_token_program,
+                ) {
+                    (Some(mint), Some(from), Some(to), Some(program)) => (mint, from, to, program),
+                    _ => return err!(CustomError::MissingCreationFeeAccounts),
+                };
+
+                token_interface::transfer_checked(
+                    CpiContext::new(
+                        fee_token_program.to_account_info(),
+                        TransferChecked {
+                            from: creator_fee_wallet.to_account_info(),
+                            mint: fee_mint.to_account_info(),
+                            to: treasury_fee_wallet.to_account_info(),
+                            authority: ctx.accounts.creator.to_account_info(),
+                        },
+                    ),
+                    pool_creation.fee,
+                    fee_mint.decimals,
+                )?;
+            }
+        }
+
         let torrent = &mut ctx.accounts.torrent;
         let pool = &mut ctx.accounts.pool;
 
-        let pool_index = torrent.register_pool(pool.key()).unwrap();
+        let pool_index = torrent.register_pool(pool.key())?;
         pool.index = pool_index;
         pool.torrent = torrent.key();
+        pool.fee_tier = fee_tier;
+        pool.fee_bps = fee_tier;
+        pool.creator = ctx.accounts.creator.key();
 
-        //let mint_amount = (initial_x + initial_y) >> 1;
-        let mint_amount = initial_x
-            .checked_add(initial_y)
-            .unwrap()
-            .checked_div(2 as u64)
-            .unwrap();
+        // Liquidity is minted for what the vaults actually receive
+        let x_received =
+            amount_after_transfer_fee(&ctx.accounts.mint_x.to_account_info(), initial_x)?;
+        let y_received =
+            amount_after_transfer_fee(&ctx.accounts.mint_y.to_account_info(), initial_y)?;
+
+        let mint_amount = torrent_math::initial_lp_tokens(x_received, y_received).unwrap();
 
         pool.pool_liquidity = pool.pool_liquidity.checked_add(mint_amount).unwrap();
         torrent.torrent_liquidity = torrent.torrent_liquidity.checked_add(mint_amount).unwrap();
 
         let torrent_bump = torrent.bump;
-        let authority = ctx.accounts.authority.key();
-        let torrent_signature = &[b"torrent", authority.as_ref(), &[torrent_bump]];
+        let authority = torrent.authority;
+        let torrent_signature = &[pda::TORRENT_SEED, authority.as_ref(), &[torrent_bump]];
 
         anchor_spl::token::mint_to(
             CpiContext::new(
@@ -54,7 +291,7 @@ pub mod torrent {
                 MintTo {
                     to: ctx
                         .accounts
-                        .authority_liquidity_token_wallet
+                        .creator_liquidity_token_wallet
                         .to_account_info(),
                     mint: ctx.accounts.liquidity_token_mint.to_account_info(),
                     authority: ctx.accounts.torrent.to_account_info(),
@@ -64,62 +301,163 @@ pub mod torrent {
             mint_amount,
         )?;
 
-        anchor_spl::token::transfer(
+        token_interface::transfer_checked(
             CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.authority_x_wallet.to_account_info(),
+                ctx.accounts.token_x_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.creator_x_wallet.to_account_info(),
+                    mint: ctx.accounts.mint_x.to_account_info(),
                     to: ctx.accounts.x_token_vault.to_account_info(),
-                    authority: ctx.accounts.authority.to_account_info(),
+                    authority: ctx.accounts.creator.to_account_info(),
                 },
             ),
             initial_x,
+            ctx.accounts.mint_x.decimals,
         )?;
 
-        anchor_spl::token::transfer(
+        token_interface::transfer_checked(
             CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.authority_y_wallet.to_account_info(),
+                ctx.accounts.token_y_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.creator_y_wallet.to_account_info(),
+                    mint: ctx.accounts.mint_y.to_account_info(),
                     to: ctx.accounts.y_token_vault.to_account_info(),
-                    authority: ctx.accounts.authority.to_account_info(),
+                    authority: ctx.accounts.creator.to_account_info(),
                 },
             ),
             initial_y,
+            ctx.accounts.mint_y.decimals,
         )?;
 
         Ok(())
     }
 
+    pub fn delist_pool(ctx: Context<DelistPool>) -> Result<()> {
+        ctx.accounts
+            .torrent
+            .multisig
+            .check(ctx.remaining_accounts)?;
+
+        let pool = &mut ctx.accounts.pool;
+        ctx.accounts.torrent.pools[pool.index as usize] = Pubkey::default();
+        pool.delisted = true;
+
+        Ok(())
+    }
+
+    pub fn set_allow_list(ctx: Context<UpdatePool>, enabled: bool) -> Result<()> {
+        ctx.accounts
+            .torrent
+            .multisig
+            .check(ctx.remaining_accounts)?;
+
+        ctx.accounts.pool.allow_list = enabled;
+
+        Ok(())
+    }
+
+    pub fn issue_allowance(ctx: Context<IssueAllowance>) -> Result<()> {
+        // The gatekeeper onboards users one at a time without the multisig. An allowance
+        // only opens pools the multisig already put behind the allow list, and can be revoked
+        let allowance = &mut ctx.accounts.allowance;
+        allowance.torrent = ctx.accounts.torrent.key();
+        allowance.user = ctx.accounts.user.key();
+        allowance.bump = *ctx.bumps.get("allowance").unwrap();
+
+        Ok(())
+    }
+
+    pub fn revoke_allowance(_ctx: Context<RevokeAllowance>) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn set_deposit_caps(
+        ctx: Context<UpdatePool>,
+        max_reserve_x: u64,
+        max_reserve_y: u64,
+        max_pool_liquidity: u64,
+    ) -> Result<()> {
+        ctx.accounts
+            .torrent
+            .multisig
+            .check(ctx.remaining_accounts)?;
+
+        let pool = &mut ctx.accounts.pool;
+        pool.max_reserve_x = max_reserve_x;
+        pool.max_reserve_y = max_reserve_y;
+        pool.max_pool_liquidity = max_pool_liquidity;
+
+        Ok(())
+    }
+
     pub fn add_liquidity(
         ctx: Context<AlterLiquidity>,
         amount_x: u64,
         _amount_y: u64,
+        deadline: Option<i64>,
     ) -> Result<()> {
+        check_deadline(deadline)?;
+        require!(!ctx.accounts.torrent.paused, CustomError::Paused);
+        check_allowance(&ctx.accounts.pool, &ctx.accounts.allowance)?;
+        require!(!ctx.accounts.pool.delisted, CustomError::PoolDelisted);
+
         let x_vault_balance = ctx.accounts.x_token_vault.amount;
         let y_vault_balance = ctx.accounts.y_token_vault.amount;
 
         let x_deposit = amount_x;
-        let y_deposit = y_vault_balance
-            .checked_mul(x_deposit)
-            .unwrap()
-            .checked_div(x_vault_balance)
-            .unwrap();
+        let x_received =
+            amount_after_transfer_fee(&ctx.accounts.mint_x.to_account_info(), x_deposit)?;
+        let y_deposit =
+            torrent_math::deposit_amount(x_received, x_vault_balance, y_vault_balance).unwrap();
+        let y_received =
+            amount_after_transfer_fee(&ctx.accounts.mint_y.to_account_info(), y_deposit)?;
 
-        let user_x_balance = ctx.accounts.user_x_wallet.amount;
-        let user_y_balance = ctx.accounts.user_y_wallet.amount;
-        require!(user_x_balance >= x_deposit, CustomError::InadequateBalance);
-        require!(user_y_balance >= y_deposit, CustomError::InadequateBalance);
+        let native_wallet = NativeWallet::new(
+            &ctx.accounts.native_wallet,
+            &ctx.bumps,
+            ctx.accounts.pool.key(),
+            &ctx.accounts.system_program,
+        );
+        let user_x_wallet = open_user_wallet(
+            &ctx.accounts.user_x_wallet,
+            &native_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.mint_x,
+            &ctx.accounts.token_x_program,
+            x_deposit,
+        )?;
+        let user_y_wallet = open_user_wallet(
+            &ctx.accounts.user_y_wallet,
+            &native_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.mint_y,
+            &ctx.accounts.token_y_program,
+            y_deposit,
+        )?;
 
-        let pool_liquidity = ctx.accounts.pool.pool_liquidity;
+        require!(
+            user_x_wallet.amount >= x_deposit,
+            CustomError::InadequateBalance
+        );
+        require!(
+            user_y_wallet.amount >= y_deposit,
+            CustomError::InadequateBalance
+        );
 
-        // Does this work? Looks like multiplication is guaranteed to fail.
-        // Test on code completion and apply fix if needed.
-        let mint_amount = ((x_deposit as u128)
-            .checked_mul(pool_liquidity as u128)
-            .unwrap()
-            .checked_div(x_vault_balance as u128)
-            .unwrap()) as u64;
+        let pool_liquidity = ctx.accounts.pool.pool_liquidity;
+        let mint_amount = torrent_math::lp_tokens_for_deposit(
+            x_received,
+            y_received,
+            x_vault_balance,
+            y_vault_balance,
+            pool_liquidity,
+        )
+        .unwrap();
+        ctx.accounts.pool.check_deposit_caps(
+            x_vault_balance.checked_add(x_received).unwrap(),
+            y_vault_balance.checked_add(y_received).unwrap(),
+            pool_liquidity.checked_add(mint_amount).unwrap(),
+        )?;
 
         let pool = &mut ctx.accounts.pool;
         let torrent = &mut ctx.accounts.torrent;
@@ -128,7 +466,11 @@ pub mod torrent {
 
         let torrent_bump = *ctx.bumps.get("torrent").unwrap();
         let torrent_authority = torrent.authority;
-        let torrent_signature = &[b"torrent", torrent_authority.as_ref(), &[torrent_bump]];
+        let torrent_signature = &[
+            pda::TORRENT_SEED,
+            torrent_authority.as_ref(),
+            &[torrent_bump],
+        ];
 
         anchor_spl::token::mint_to(
             CpiContext::new(
@@ -143,34 +485,190 @@ pub mod torrent {
             mint_amount,
         )?;
 
-        anchor_spl::token::transfer(
+        token_interface::transfer_checked(
             CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.user_x_wallet.to_account_info(),
+                ctx.accounts.token_x_program.to_account_info(),
+                TransferChecked {
+                    from: user_x_wallet.account.clone(),
+                    mint: ctx.accounts.mint_x.to_account_info(),
                     to: ctx.accounts.x_token_vault.to_account_info(),
                     authority: ctx.accounts.user.to_account_info(),
                 },
             ),
             x_deposit,
+            ctx.accounts.mint_x.decimals,
         )?;
 
-        anchor_spl::token::transfer(
+        token_interface::transfer_checked(
             CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.user_y_wallet.to_account_info(),
+                ctx.accounts.token_y_program.to_account_info(),
+                TransferChecked {
+                    from: user_y_wallet.account.clone(),
+                    mint: ctx.accounts.mint_y.to_account_info(),
                     to: ctx.accounts.y_token_vault.to_account_info(),
                     authority: ctx.accounts.user.to_account_info(),
                 },
             ),
             y_deposit,
+            ctx.accounts.mint_y.decimals,
+        )?;
+
+        close_user_wallet(
+            &user_x_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.token_x_program,
+        )?;
+        close_user_wallet(
+            &user_y_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.token_y_program,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn add_liquidity_single(
+        ctx: Context<AlterLiquidity>,
+        amount_in: u64,
+        min_lp_out: u64,
+        side: PoolSide,
+        deadline: Option<i64>,
+    ) -> Result<()> {
+        check_deadline(deadline)?;
+        require!(!ctx.accounts.torrent.paused, CustomError::Paused);
+        check_allowance(&ctx.accounts.pool, &ctx.accounts.allowance)?;
+        require!(!ctx.accounts.pool.delisted, CustomError::PoolDelisted);
+
+        let accounts = &ctx.accounts;
+        let (user_in_wallet, vault_in, mint_in, token_program_in, vault_out) = match side {
+            PoolSide::X => (
+                &accounts.user_x_wallet,
+                &accounts.x_token_vault,
+                &accounts.mint_x,
+                &accounts.token_x_program,
+                &accounts.y_token_vault,
+            ),
+            PoolSide::Y => (
+                &accounts.user_y_wallet,
+                &accounts.y_token_vault,
+                &accounts.mint_y,
+                &accounts.token_y_program,
+                &accounts.x_token_vault,
+            ),
+        };
+        let native_wallet = NativeWallet::new(
+            &accounts.native_wallet,
+            &ctx.bumps,
+            accounts.pool.key(),
+            &accounts.system_program,
+        );
+        let user_in_wallet = open_user_wallet(
+            user_in_wallet,
+            &native_wallet,
+            &accounts.user,
+            mint_in,
+            token_program_in,
+            amount_in,
+        )?;
+        require!(
+            user_in_wallet.amount >= amount_in,
+            CustomError::InadequateBalance
+        );
+
+        let reserve_in = vault_in.amount;
+        let reserve_out = vault_out.amount;
+        let amount_received = amount_after_transfer_fee(&mint_in.to_account_info(), amount_in)?;
+
+        // Swap just enough of the deposit that what's left and the swap output sit in
+        // the post-swap reserve ratio. The swap output never leaves the vault.
+        let pool = &accounts.pool;
+        let slot = Clock::get()?.slot;
+        let fee_bps = pool.current_fee_bps(slot);
+        let swap_amount =
+            torrent_math::single_sided_swap_amount(amount_received, reserve_in, fee_bps).unwrap();
+        let swap_output =
+            torrent_math::swap_output(swap_amount, reserve_in, reserve_out, fee_bps).unwrap();
+
+        let mint_amount = torrent_math::lp_tokens_for_deposit(
+            amount_received.checked_sub(swap_amount).unwrap(),
+            swap_output,
+            reserve_in.checked_add(swap_amount).unwrap(),
+            reserve_out.checked_sub(swap_output).unwrap(),
+            pool.pool_liquidity,
+        )
+        .unwrap();
+        require!(mint_amount >= min_lp_out, CustomError::SlippageExceeded);
+
+        let reserve_in_after = reserve_in.checked_add(amount_received).unwrap();
+        let (reserve_x_after, reserve_y_after) = match side {
+            PoolSide::X => (reserve_in_after, reserve_out),
+            PoolSide::Y => (reserve_out, reserve_in_after),
+        };
+        pool.check_deposit_caps(
+            reserve_x_after,
+            reserve_y_after,
+            pool.pool_liquidity.checked_add(mint_amount).unwrap(),
+        )?;
+
+        let torrent_bump = accounts.torrent.bump;
+        let torrent_authority = accounts.torrent.authority;
+        let torrent_signature = &[
+            pda::TORRENT_SEED,
+            torrent_authority.as_ref(),
+            &[torrent_bump],
+        ];
+
+        token_interface::transfer_checked(
+            CpiContext::new(
+                token_program_in.to_account_info(),
+                TransferChecked {
+                    from: user_in_wallet.account.clone(),
+                    mint: mint_in.to_account_info(),
+                    to: vault_in.to_account_info(),
+                    authority: accounts.user.to_account_info(),
+                },
+            ),
+            amount_in,
+            mint_in.decimals,
+        )?;
+
+        anchor_spl::token::mint_to(
+            CpiContext::new(
+                accounts.token_program.to_account_info(),
+                MintTo {
+                    to: accounts.user_liquidity_token_wallet.to_account_info(),
+                    mint: accounts.liquidity_token_mint.to_account_info(),
+                    authority: accounts.torrent.to_account_info(),
+                },
+            )
+            .with_signer(&[&torrent_signature[..]]),
+            mint_amount,
         )?;
 
+        close_user_wallet(&user_in_wallet, &accounts.user, token_program_in)?;
+
+        let pool = &mut ctx.accounts.pool;
+        let torrent = &mut ctx.accounts.torrent;
+        pool.record_price_move(
+            reserve_in,
+            reserve_out,
+            reserve_in.checked_add(swap_amount).unwrap(),
+            reserve_out.checked_sub(swap_output).unwrap(),
+            slot,
+        );
+        pool.pool_liquidity = pool.pool_liquidity.checked_add(mint_amount).unwrap();
+        torrent.torrent_liquidity = torrent.torrent_liquidity.checked_add(mint_amount).unwrap();
+
         Ok(())
     }
 
-    pub fn remove_liquidity(ctx: Context<AlterLiquidity>, lt_amount: u64) -> Result<()> {
+    pub fn remove_liquidity(
+        ctx: Context<AlterLiquidity>,
+        lt_amount: u64,
+        deadline: Option<i64>,
+    ) -> Result<()> {
+        check_deadline(deadline)?;
+
         let user_lt_balance = ctx.accounts.user_liquidity_token_wallet.amount;
         require!(user_lt_balance >= lt_amount, CustomError::InadequateBalance);
         let pool_liquidity = ctx.accounts.pool.pool_liquidity;
@@ -179,17 +677,33 @@ pub mod torrent {
         let x_vault_balance = ctx.accounts.x_token_vault.amount;
         let y_vault_balance = ctx.accounts.y_token_vault.amount;
 
-        // Does this work?
-        let x_owed = ((lt_amount as u128)
-            .checked_mul(x_vault_balance as u128)
-            .unwrap()
-            .checked_div(pool_liquidity as u128)
-            .unwrap()) as u64;
-        let y_owed = ((lt_amount as u128)
-            .checked_mul(y_vault_balance as u128)
-            .unwrap()
-            .checked_div(pool_liquidity as u128)
-            .unwrap()) as u64;
+        let x_owed =
+            torrent_math::withdraw_amount(lt_amount, x_vault_balance, pool_liquidity).unwrap();
+        let y_owed =
+            torrent_math::withdraw_amount(lt_amount, y_vault_balance, pool_liquidity).unwrap();
+
+        let native_wallet = NativeWallet::new(
+            &ctx.accounts.native_wallet,
+            &ctx.bumps,
+            ctx.accounts.pool.key(),
+            &ctx.accounts.system_program,
+        );
+        let user_x_wallet = open_user_wallet(
+            &ctx.accounts.user_x_wallet,
+            &native_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.mint_x,
+            &ctx.accounts.token_x_program,
+            0,
+        )?;
+        let user_y_wallet = open_user_wallet(
+            &ctx.accounts.user_y_wallet,
+            &native_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.mint_y,
+            &ctx.accounts.token_y_program,
+            0,
+        )?;
 
         let pool = &mut ctx.accounts.pool;
         let torrent = &mut ctx.accounts.torrent;
@@ -201,38 +715,44 @@ pub mod torrent {
         let x_token_mint = ctx.accounts.x_token_vault.mint;
         let y_token_mint = ctx.accounts.y_token_vault.mint;
         let torrent_key = torrent.key();
+        let fee_tier = pool.fee_tier.to_le_bytes();
 
         let pool_signature = &[
             torrent_key.as_ref(),
             x_token_mint.as_ref(),
             y_token_mint.as_ref(),
+            fee_tier.as_ref(),
             &[pool_bump],
         ];
 
-        anchor_spl::token::transfer(
+        token_interface::transfer_checked(
             CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
+                ctx.accounts.token_x_program.to_account_info(),
+                TransferChecked {
                     from: ctx.accounts.x_token_vault.to_account_info(),
-                    to: ctx.accounts.user_x_wallet.to_account_info(),
+                    mint: ctx.accounts.mint_x.to_account_info(),
+                    to: user_x_wallet.account.clone(),
                     authority: ctx.accounts.pool.to_account_info(),
                 },
             )
             .with_signer(&[&pool_signature[..]]),
             x_owed,
+            ctx.accounts.mint_x.decimals,
         )?;
 
-        anchor_spl::token::transfer(
+        token_interface::transfer_checked(
             CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.x_token_vault.to_account_info(),
-                    to: ctx.accounts.user_x_wallet.to_account_info(),
+                ctx.accounts.token_y_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.y_token_vault.to_account_info(),
+                    mint: ctx.accounts.mint_y.to_account_info(),
+                    to: user_y_wallet.account.clone(),
                     authority: ctx.accounts.pool.to_account_info(),
                 },
             )
             .with_signer(&[&pool_signature[..]]),
             y_owed,
+            ctx.accounts.mint_y.decimals,
         )?;
 
         anchor_spl::token::burn(
@@ -247,208 +767,3178 @@ pub mod torrent {
             lt_amount,
         )?;
 
+        close_user_wallet(
+            &user_x_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.token_x_program,
+        )?;
+        close_user_wallet(
+            &user_y_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.token_y_program,
+        )?;
+
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-#[instruction(decimals: u8)]
-pub struct InitializeTorrent<'info> {
-    #[account(mut)]
-    authority: Signer<'info>,
+    pub fn remove_liquidity_single(
+        ctx: Context<AlterLiquidity>,
+        lt_amount: u64,
+        min_amount_out: u64,
+        side: PoolSide,
+        deadline: Option<i64>,
+    ) -> Result<()> {
+        check_deadline(deadline)?;
+        // Unlike a proportional withdrawal this swaps against the pool
+        require!(!ctx.accounts.torrent.paused, CustomError::Paused);
+        check_allowance(&ctx.accounts.pool, &ctx.accounts.allowance)?;
 
-    /// Stores torrent state
-    #[account(
-        init,
-        seeds = [b"torrent".as_ref(), authority.key().as_ref()],
-        bump,
-        payer = authority,
-        space = 8 + Torrent::SIZE
-    )]
-    torrent: Account<'info, Torrent>,
+        let user_lt_balance = ctx.accounts.user_liquidity_token_wallet.amount;
+        require!(user_lt_balance >= lt_amount, CustomError::InadequateBalance);
+        let pool_liquidity = ctx.accounts.pool.pool_liquidity;
+        require!(pool_liquidity >= lt_amount, CustomError::ExcessiveBurn);
 
-    /// This is the liquidity token for torrent's pools
-    #[account(
-        init,
-        seeds = [b"token".as_ref(), torrent.key().as_ref()],
-        bump,
-        payer = authority,
-        mint::decimals = decimals,
-        mint::authority = torrent,
-    )]
-    liquidity_token: Account<'info, Mint>,
+        let accounts = &ctx.accounts;
+        let (user_out_wallet, vault_out, mint_out, token_program_out, vault_in) = match side {
+            PoolSide::X => (
+                &accounts.user_x_wallet,
+                &accounts.x_token_vault,
+                &accounts.mint_x,
+                &accounts.token_x_program,
+                &accounts.y_token_vault,
+            ),
+            PoolSide::Y => (
+                &accounts.user_y_wallet,
+                &accounts.y_token_vault,
+                &accounts.mint_y,
+                &accounts.token_y_program,
+                &accounts.x_token_vault,
+            ),
+        };
+        let native_wallet = NativeWallet::new(
+            &accounts.native_wallet,
+            &ctx.bumps,
+            accounts.pool.key(),
+            &accounts.system_program,
+        );
+        let user_out_wallet = open_user_wallet(
+            user_out_wallet,
+            &native_wallet,
+            &accounts.user,
+            mint_out,
+            token_program_out,
+            0,
+        )?;
 
-    system_program: Program<'info, System>,
-    token_program: Program<'info, Token>,
-    rent: Sysvar<'info, Rent>,
-}
+        let reserve_out = vault_out.amount;
+        let reserve_in = vault_in.amount;
 
-#[derive(Accounts)]
-#[instruction(initial_x: u64, initial_y: u64)]
-pub struct InitializePool<'info> {
-    #[account(mut, has_one = authority, has_one = liquidity_token_mint)]
-    torrent: Box<Account<'info, Torrent>>,
-    #[account(mut)]
-    liquidity_token_mint: Box<Account<'info, Mint>>,
+        let owed_out =
+            torrent_math::withdraw_amount(lt_amount, reserve_out, pool_liquidity).unwrap();
+        let owed_in = torrent_math::withdraw_amount(lt_amount, reserve_in, pool_liquidity).unwrap();
 
-    #[account(mut)]
-    authority: Signer<'info>,
+        // The unwanted side is swapped against what's left in the pool after the
+        // withdrawal and never leaves the vault.
+        let slot = Clock::get()?.slot;
+        let swap_reserve_in = reserve_in.checked_sub(owed_in).unwrap();
+        let swap_reserve_out = reserve_out.checked_sub(owed_out).unwrap();
+        let swap_output = torrent_math::swap_output(
+            owed_in,
+            swap_reserve_in,
+            swap_reserve_out,
+            accounts.pool.current_fee_bps(slot),
+        )
+        .unwrap();
+        let amount_out = owed_out.checked_add(swap_output).unwrap();
+        require!(amount_out >= min_amount_out, CustomError::SlippageExceeded);
 
-    /// Token pair for this pool
-    mint_x: Box<Account<'info, Mint>>,
-    mint_y: Box<Account<'info, Mint>>,
+        let pool_bump = *ctx.bumps.get("pool").unwrap();
+        let x_token_mint = accounts.x_token_vault.mint;
+        let y_token_mint = accounts.y_token_vault.mint;
+        let torrent_key = accounts.torrent.key();
+        let fee_tier = accounts.pool.fee_tier.to_le_bytes();
 
-    /// Authority's token accounts
-    #[account(
-        mut,
-        constraint = authority_x_wallet.owner == authority.key(),
-        constraint = authority_x_wallet.mint == mint_x.key(),
-        constraint = authority_x_wallet.amount >= initial_x @CustomError::InadequateBalance
-    )]
-    authority_x_wallet: Box<Account<'info, TokenAccount>>,
-    #[account(
-        mut,
-        constraint = authority_y_wallet.owner == authority.key(),
-        constraint = authority_y_wallet.mint == mint_y.key(),
-        constraint = authority_y_wallet.amount >= initial_y @ CustomError::InadequateBalance
-    )]
-    authority_y_wallet: Box<Account<'info, TokenAccount>>,
-    #[account(
-        mut,
-        constraint = authority_liquidity_token_wallet.owner == authority.key(),
-        constraint = authority_liquidity_token_wallet.mint == liquidity_token_mint.key()
-    )]
-    authority_liquidity_token_wallet: Box<Account<'info, TokenAccount>>,
+        let pool_signature = &[
+            torrent_key.as_ref(),
+            x_token_mint.as_ref(),
+            y_token_mint.as_ref(),
+            fee_tier.as_ref(),
+            &[pool_bump],
+        ];
 
-    /// Stores pool state
-    #[account(
-        init,
-        seeds = [torrent.key().as_ref(), mint_x.key().as_ref(), mint_y.key().as_ref()],
-        bump,
-        payer = authority,
-        space = 8 + Pool::SIZE,
-    )]
-    pool: Box<Account<'info, Pool>>,
+        token_interface::transfer_checked(
+            CpiContext::new(
+                token_program_out.to_account_info(),
+                TransferChecked {
+                    from: vault_out.to_account_info(),
+                    mint: mint_out.to_account_info(),
+                    to: user_out_wallet.account.clone(),
+                    authority: accounts.pool.to_account_info(),
+                },
+            )
+            .with_signer(&[&pool_signature[..]]),
+            amount_out,
+            mint_out.decimals,
+        )?;
 
-    /// Vault for storing x_tokens
-    #[account(
-        init,
-        seeds = [b"x_vault".as_ref(), pool.key().as_ref()],
-        bump,
-        payer = authority,
-        token::mint = mint_x,
-        token::authority = pool
-    )]
-    x_token_vault: Box<Account<'info, TokenAccount>>,
+        anchor_spl::token::burn(
+            CpiContext::new(
+                accounts.token_program.to_account_info(),
+                Burn {
+                    mint: accounts.liquidity_token_mint.to_account_info(),
+                    from: accounts.user_liquidity_token_wallet.to_account_info(),
+                    authority: accounts.user.to_account_info(),
+                },
+            ),
+            lt_amount,
+        )?;
 
-    /// Vault for storing y_tokens
-    #[account(
-        init,
-        seeds = [b"y_vault".as_ref(), pool.key().as_ref()],
-        bump,
-        payer = authority,
-        token::mint = mint_y,
-        token::authority = pool
-    )]
-    y_token_vault: Box<Account<'info, TokenAccount>>,
+        close_user_wallet(&user_out_wallet, &accounts.user, token_program_out)?;
 
-    /// System accounts
-    system_program: Program<'info, System>,
-    token_program: Program<'info, Token>,
-    rent: Sysvar<'info, Rent>,
-}
+        let pool = &mut ctx.accounts.pool;
+        let torrent = &mut ctx.accounts.torrent;
+        pool.record_price_move(
+            swap_reserve_in,
+            swap_reserve_out,
+            reserve_in,
+            swap_reserve_out.checked_sub(swap_output).unwrap(),
+            slot,
+        );
+        pool.pool_liquidity = pool.pool_liquidity.checked_sub(lt_amount).unwrap();
+        torrent.torrent_liquidity = torrent.torrent_liquidity.checked_sub(lt_amount).unwrap();
+
+        Ok(())
+    }
+
+    pub fn open_position(
+        ctx: Context<OpenPosition>,
+        amount_x: u64,
+        unlock_timestamp: i64,
+        deadline: Option<i64>,
+    ) -> Result<()> {
+        check_deadline(deadline)?;
+        require!(!ctx.accounts.torrent.paused, CustomError::Paused);
+        check_allowance(&ctx.accounts.pool, &ctx.accounts.allowance)?;
+
+        let x_vault_balance = ctx.accounts.x_token_vault.amount;
+        let y_vault_balance = ctx.accounts.y_token_vault.amount;
+
+        let x_deposit = amount_x;
+        let x_received =
+            amount_after_transfer_fee(&ctx.accounts.mint_x.to_account_info(), x_deposit)?;
+        let y_deposit =
+            torrent_math::deposit_amount(x_received, x_vault_balance, y_vault_balance).unwrap();
+        let y_received =
+            amount_after_transfer_fee(&ctx.accounts.mint_y.to_account_info(), y_deposit)?;
+
+        let native_wallet = NativeWallet::new(
+            &ctx.accounts.native_wallet,
+            &ctx.bumps,
+            ctx.accounts.pool.key(),
+            &ctx.accounts.system_program,
+        );
+        let user_x_wallet = open_user_wallet(
+            &ctx.accounts.user_x_wallet,
+            &native_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.mint_x,
+            &ctx.accounts.token_x_program,
+            x_deposit,
+        )?;
+        let user_y_wallet = open_user_wallet(
+            &ctx.accounts.user_y_wallet,
+            &native_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.mint_y,
+            &ctx.accounts.token_y_program,
+            y_deposit,
+        )?;
+
+        require!(
+            user_x_wallet.amount >= x_deposit,
+            CustomError::InadequateBalance
+        );
+        require!(
+            user_y_wallet.amount >= y_deposit,
+            CustomError::InadequateBalance
+        );
+
+        let pool_liquidity = ctx.accounts.pool.pool_liquidity;
+        let liquidity = torrent_math::lp_tokens_for_deposit(
+            x_received,
+            y_received,
+            x_vault_balance,
+            y_vault_balance,
+            pool_liquidity,
+        )
+        .unwrap();
+        ctx.accounts.pool.check_deposit_caps(
+            x_vault_balance.checked_add(x_received).unwrap(),
+            y_vault_balance.checked_add(y_received).unwrap(),
+            pool_liquidity.checked_add(liquidity).unwrap(),
+        )?;
+
+        // Position liquidity shares the pool's accounting with liquidity tokens but is
+        // not part of the torrent's liquidity token supply.
+        let pool = &mut ctx.accounts.pool;
+        pool.pool_liquidity = pool.pool_liquidity.checked_add(liquidity).unwrap();
+
+        let position = &mut ctx.accounts.position;
+        position.pool = pool.key();
+        position.position_mint = ctx.accounts.position_mint.key();
+        position.liquidity = liquidity;
+        position.unlock_timestamp = unlock_timestamp;
+        position.bump = *ctx.bumps.get("position").unwrap();
+
+        let torrent_bump = ctx.accounts.torrent.bump;
+        let torrent_authority = ctx.accounts.torrent.authority;
+        let torrent_signature = &[
+            pda::TORRENT_SEED,
+            torrent_authority.as_ref(),
+            &[torrent_bump],
+        ];
+
+        anchor_spl::token::mint_to(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                MintTo {
+                    to: ctx.accounts.user_position_token_wallet.to_account_info(),
+                    mint: ctx.accounts.position_mint.to_account_info(),
+                    authority: ctx.accounts.torrent.to_account_info(),
+                },
+            )
+            .with_signer(&[&torrent_signature[..]]),
+            1,
+        )?;
+
+        // Fix the supply at one so the position can't be split.
+        anchor_spl::token::set_authority(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                SetAuthority {
+                    current_authority: ctx.accounts.torrent.to_account_info(),
+                    account_or_mint: ctx.accounts.position_mint.to_account_info(),
+                },
+            )
+            .with_signer(&[&torrent_signature[..]]),
+            AuthorityType::MintTokens,
+            None,
+        )?;
+
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_x_program.to_account_info(),
+                TransferChecked {
+                    from: user_x_wallet.account.clone(),
+                    mint: ctx.accounts.mint_x.to_account_info(),
+                    to: ctx.accounts.x_token_vault.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            x_deposit,
+            ctx.accounts.mint_x.decimals,
+        )?;
+
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_y_program.to_account_info(),
+                TransferChecked {
+                    from: user_y_wallet.account.clone(),
+                    mint: ctx.accounts.mint_y.to_account_info(),
+                    to: ctx.accounts.y_token_vault.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            y_deposit,
+            ctx.accounts.mint_y.decimals,
+        )?;
+
+        close_user_wallet(
+            &user_x_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.token_x_program,
+        )?;
+        close_user_wallet(
+            &user_y_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.token_y_program,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn add_position_liquidity(
+        ctx: Context<AlterPositionLiquidity>,
+        amount_x: u64,
+        deadline: Option<i64>,
+    ) -> Result<()> {
+        check_deadline(deadline)?;
+        require!(!ctx.accounts.torrent.paused, CustomError::Paused);
+        check_allowance(&ctx.accounts.pool, &ctx.accounts.allowance)?;
+        require!(!ctx.accounts.pool.delisted, CustomError::PoolDelisted);
+
+        let x_vault_balance = ctx.accounts.x_token_vault.amount;
+        let y_vault_balance = ctx.accounts.y_token_vault.amount;
+
+        let x_deposit = amount_x;
+        let x_received =
+            amount_after_transfer_fee(&ctx.accounts.mint_x.to_account_info(), x_deposit)?;
+        let y_deposit =
+            torrent_math::deposit_amount(x_received, x_vault_balance, y_vault_balance).unwrap();
+        let y_received =
+            amount_after_transfer_fee(&ctx.accounts.mint_y.to_account_info(), y_deposit)?;
+
+        let native_wallet = NativeWallet::new(
+            &ctx.accounts.native_wallet,
+            &ctx.bumps,
+            ctx.accounts.pool.key(),
+            &ctx.accounts.system_program,
+        );
+        let user_x_wallet = open_user_wallet(
+            &ctx.accounts.user_x_wallet,
+            &native_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.mint_x,
+            &ctx.accounts.token_x_program,
+            x_deposit,
+        )?;
+        let user_y_wallet = open_user_wallet(
+            &ctx.accounts.user_y_wallet,
+            &native_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.mint_y,
+            &ctx.accounts.token_y_program,
+            y_deposit,
+        )?;
+
+        require!(
+            user_x_wallet.amount >= x_deposit,
+            CustomError::InadequateBalance
+        );
+        require!(
+            user_y_wallet.amount >= y_deposit,
+            CustomError::InadequateBalance
+        );
+
+        let pool_liquidity = ctx.accounts.pool.pool_liquidity;
+        let liquidity = torrent_math::lp_tokens_for_deposit(
+            x_received,
+            y_received,
+            x_vault_balance,
+            y_vault_balance,
+            pool_liquidity,
+        )
+        .unwrap();
+        ctx.accounts.pool.check_deposit_caps(
+            x_vault_balance.checked_add(x_received).unwrap(),
+            y_vault_balance.checked_add(y_received).unwrap(),
+            pool_liquidity.checked_add(liquidity).unwrap(),
+        )?;
+
+        let pool = &mut ctx.accounts.pool;
+        let position = &mut ctx.accounts.position;
+        pool.pool_liquidity = pool.pool_liquidity.checked_add(liquidity).unwrap();
+        position.liquidity = position.liquidity.checked_add(liquidity).unwrap();
+
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_x_program.to_account_info(),
+                TransferChecked {
+                    from: user_x_wallet.account.clone(),
+                    mint: ctx.accounts.mint_x.to_account_info(),
+                    to: ctx.accounts.x_token_vault.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            x_deposit,
+            ctx.accounts.mint_x.decimals,
+        )?;
+
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_y_program.to_account_info(),
+                TransferChecked {
+                    from: user_y_wallet.account.clone(),
+                    mint: ctx.accounts.mint_y.to_account_info(),
+                    to: ctx.accounts.y_token_vault.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            y_deposit,
+            ctx.accounts.mint_y.decimals,
+        )?;
+
+        close_user_wallet(
+            &user_x_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.token_x_program,
+        )?;
+        close_user_wallet(
+            &user_y_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.token_y_program,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn remove_position_liquidity(
+        ctx: Context<AlterPositionLiquidity>,
+        liquidity: u64,
+        deadline: Option<i64>,
+    ) -> Result<()> {
+        check_deadline(deadline)?;
+
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            now >= ctx.accounts.position.unlock_timestamp,
+            CustomError::PositionLocked
+        );
+        require!(
+            ctx.accounts.position.liquidity >= liquidity,
+            CustomError::ExcessiveBurn
+        );
+        let pool_liquidity = ctx.accounts.pool.pool_liquidity;
+
+        let x_vault_balance = ctx.accounts.x_token_vault.amount;
+        let y_vault_balance = ctx.accounts.y_token_vault.amount;
+
+        let x_owed =
+            torrent_math::withdraw_amount(liquidity, x_vault_balance, pool_liquidity).unwrap();
+        let y_owed =
+            torrent_math::withdraw_amount(liquidity, y_vault_balance, pool_liquidity).unwrap();
+
+        let native_wallet = NativeWallet::new(
+            &ctx.accounts.native_wallet,
+            &ctx.bumps,
+            ctx.accounts.pool.key(),
+            &ctx.accounts.system_program,
+        );
+        let user_x_wallet = open_user_wallet(
+            &ctx.accounts.user_x_wallet,
+            &native_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.mint_x,
+            &ctx.accounts.token_x_program,
+            0,
+        )?;
+        let user_y_wallet = open_user_wallet(
+            &ctx.accounts.user_y_wallet,
+            &native_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.mint_y,
+            &ctx.accounts.token_y_program,
+            0,
+        )?;
+
+        let pool = &mut ctx.accounts.pool;
+        let position = &mut ctx.accounts.position;
+        pool.pool_liquidity = pool.pool_liquidity.checked_sub(liquidity).unwrap();
+        position.liquidity = position.liquidity.checked_sub(liquidity).unwrap();
+
+        let pool_bump = *ctx.bumps.get("pool").unwrap();
+        let x_token_mint = ctx.accounts.x_token_vault.mint;
+        let y_token_mint = ctx.accounts.y_token_vault.mint;
+        let torrent_key = ctx.accounts.torrent.key();
+        let fee_tier = ctx.accounts.pool.fee_tier.to_le_bytes();
+
+        let pool_signature = &[
+            torrent_key.as_ref(),
+            x_token_mint.as_ref(),
+            y_token_mint.as_ref(),
+            fee_tier.as_ref(),
+            &[pool_bump],
+        ];
+
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_x_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.x_token_vault.to_account_info(),
+                    mint: ctx.accounts.mint_x.to_account_info(),
+                    to: user_x_wallet.account.clone(),
+                    authority: ctx.accounts.pool.to_account_info(),
+                },
+            )
+            .with_signer(&[&pool_signature[..]]),
+            x_owed,
+            ctx.accounts.mint_x.decimals,
+        )?;
+
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_y_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.y_token_vault.to_account_info(),
+                    mint: ctx.accounts.mint_y.to_account_info(),
+                    to: user_y_wallet.account.clone(),
+                    authority: ctx.accounts.pool.to_account_info(),
+                },
+            )
+            .with_signer(&[&pool_signature[..]]),
+            y_owed,
+            ctx.accounts.mint_y.decimals,
+        )?;
+
+        close_user_wallet(
+            &user_x_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.token_x_program,
+        )?;
+        close_user_wallet(
+            &user_y_wallet,
+            &ctx.accounts.user,
+            &ctx.accounts.token_y_program,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn swap(
+        ctx: Context<Swap>,
+        amount_in: u64,
+        min_amount_out: u64,
+        direction: SwapDirection,
+        deadline: Option<i64>,
+    ) -> Result<()> {
+        check_deadline(deadline)?;
+        require!(!ctx.accounts.torrent.paused, CustomError::Paused);
+        check_allowance(&ctx.accounts.pool, &ctx.accounts.allowance)?;
+
+        let accounts = &ctx.accounts;
+        let (
+            user_in_wallet,
+            vault_in,
+            mint_in,
+            token_program_in,
+            user_out_wallet,
+            vault_out,
+            mint_out,
+            token_program_out,
+        ) = match direction {
+            SwapDirection::XToY => (
+                &accounts.user_x_wallet,
+                &accounts.x_token_vault,
+                &accounts.mint_x,
+                &accounts.token_x_program,
+                &accounts.user_y_wallet,
+                &accounts.y_token_vault,
+                &accounts.mint_y,
+                &accounts.token_y_program,
+            ),
+            SwapDirection::YToX => (
+                &accounts.user_y_wallet,
+                &accounts.y_token_vault,
+                &accounts.mint_y,
+                &accounts.token_y_program,
+                &accounts.user_x_wallet,
+                &accounts.x_token_vault,
+                &accounts.mint_x,
+                &accounts.token_x_program,
+            ),
+        };
+        let native_wallet = NativeWallet::new(
+            &accounts.native_wallet,
+            &ctx.bumps,
+            accounts.pool.key(),
+            &accounts.system_program,
+        );
+        let user_in_wallet = open_user_wallet(
+            user_in_wallet,
+            &native_wallet,
+            &accounts.user,
+            mint_in,
+            token_program_in,
+            amount_in,
+        )?;
+        let user_out_wallet = open_user_wallet(
+            user_out_wallet,
+            &native_wallet,
+            &accounts.user,
+            mint_out,
+            token_program_out,
+            0,
+        )?;
+
+        require!(
+            user_in_wallet.amount >= amount_in,
+            CustomError::InadequateBalance
+        );
+
+        let referrer_wallet = match (&accounts.referrer, &accounts.referrer_wallet) {
+            (Some(referrer), Some(wallet)) => {
+                referrer.check_swap(&accounts.user.key(), wallet, &mint_in.key())?;
+                Some(wallet)
+            }
+            (None, None) => None,
+            _ => return err!(CustomError::ReferrerMismatch),
+        };
+
+        // Volume is counted in the torrent's quote mint, so only swaps trading it build up
+        // or use a trader's volume
+        let quote_mint = accounts.torrent.volume_quote_mint;
+        let trades_quote_mint = mint_in.key() == quote_mint || mint_out.key() == quote_mint;
+
+        let clock = Clock::get()?;
+        let slot = clock.slot;
+        let fee_bps = match accounts.trader_stats.as_ref().filter(|_| trades_quote_mint) {
+            Some(trader_stats) => accounts.torrent.discounted_fee_bps(
+                accounts.pool.current_fee_bps(slot),
+                trader_stats.rolling_volume(clock.unix_timestamp),
+            ),
+            None => accounts.pool.current_fee_bps(slot),
+        };
+
+        let reserve_in = vault_in.amount;
+        let reserve_out = vault_out.amount;
+        let amount_received = amount_after_transfer_fee(&mint_in.to_account_info(), amount_in)?;
+        let amount_out =
+            torrent_math::swap_output(amount_received, reserve_in, reserve_out, fee_bps).unwrap();
+        require!(amount_out >= min_amount_out, CustomError::SlippageExceeded);
+        let quote_volume = match mint_in.key() == quote_mint {
+            true => amount_in,
+            false => amount_out,
+        };
+
+        // The referrer's cut comes out of the swap fee, so it's diverted from what the
+        // vault would otherwise keep rather than charged on top.
+        let referral_fee = match &accounts.referrer {
+            Some(referrer) => {
+                let share_bps = referrer
+                    .share_bps
+                    .min(accounts.torrent.max_referral_share_bps);
+                ((torrent_math::swap_fee(amount_received, fee_bps).unwrap() as u128)
+                    .checked_mul(share_bps as u128)
+                    .unwrap()
+                    .checked_div(BPS_DENOMINATOR as u128)
+                    .unwrap()) as u64
+            }
+            None => 0,
+        };
+        let vault_deposit = amount_in.checked_sub(referral_fee).unwrap();
+        let vault_received = amount_after_transfer_fee(&mint_in.to_account_info(), vault_deposit)?;
+
+        let pool_bump = *ctx.bumps.get("pool").unwrap();
+        let x_token_mint = accounts.x_token_vault.mint;
+        let y_token_mint = accounts.y_token_vault.mint;
+        let torrent_key = accounts.torrent.key();
+        let fee_tier = accounts.pool.fee_tier.to_le_bytes();
+
+        let pool_signature = &[
+            torrent_key.as_ref(),
+            x_token_mint.as_ref(),
+            y_token_mint.as_ref(),
+            fee_tier.as_ref(),
+            &[pool_bump],
+        ];
+
+        token_interface::transfer_checked(
+            CpiContext::new(
+                token_program_in.to_account_info(),
+                TransferChecked {
+                    from: user_in_wallet.account.clone(),
+                    mint: mint_in.to_account_info(),
+                    to: vault_in.to_account_info(),
+                    authority: accounts.user.to_account_info(),
+                },
+            ),
+            vault_deposit,
+            mint_in.decimals,
+        )?;
+
+        if let Some(referrer_wallet) = referrer_wallet.filter(|_| referral_fee > 0) {
+            token_interface::transfer_checked(
+                CpiContext::new(
+                    token_program_in.to_account_info(),
+                    TransferChecked {
+                        from: user_in_wallet.account.clone(),
+                        mint: mint_in.to_account_info(),
+                        to: referrer_wallet.to_account_info(),
+                        authority: accounts.user.to_account_info(),
+                    },
+                ),
+                referral_fee,
+                mint_in.decimals,
+            )?;
+        }
+
+        token_interface::transfer_checked(
+            CpiContext::new(
+                token_program_out.to_account_info(),
+                TransferChecked {
+                    from: vault_out.to_account_info(),
+                    mint: mint_out.to_account_info(),
+                    to: user_out_wallet.account.clone(),
+                    authority: accounts.pool.to_account_info(),
+                },
+            )
+            .with_signer(&[&pool_signature[..]]),
+            amount_out,
+            mint_out.decimals,
+        )?;
+
+        close_user_wallet(&user_in_wallet, &accounts.user, token_program_in)?;
+        close_user_wallet(&user_out_wallet, &accounts.user, token_program_out)?;
+
+        ctx.accounts.pool.record_price_move(
+            reserve_in,
+            reserve_out,
+            reserve_in.checked_add(vault_received).unwrap(),
+            reserve_out.checked_sub(amount_out).unwrap(),
+            slot,
+        );
+
+        if let Some(trader_stats) = ctx
+            .accounts
+            .trader_stats
+            .as_mut()
+            .filter(|_| trades_quote_mint)
+        {
+            trader_stats.record_volume(quote_volume, clock.unix_timestamp);
+        }
+        if let Some(referrer) = &mut ctx.accounts.referrer {
+            referrer.swap_count = referrer.swap_count.checked_add(1).unwrap();
+            referrer.volume = referrer.volume.checked_add(amount_in as u128).unwrap();
+            referrer.fees_earned = referrer
+                .fees_earned
+                .checked_add(referral_fee as u128)
+                .unwrap();
+        }
+
+        Ok(())
+    }
+
+    pub fn quote_swap(
+        ctx: Context<QuotePool>,
+        amount_in: u64,
+        direction: SwapDirection,
+    ) -> Result<SwapQuote> {
+        let (mint_in, reserve_in, reserve_out) = match direction {
+            SwapDirection::XToY => (
+                &ctx.accounts.mint_x,
+                ctx.accounts.x_token_vault.amount,
+                ctx.accounts.y_token_vault.amount,
+            ),
+            SwapDirection::YToX => (
+                &ctx.accounts.mint_y,
+                ctx.accounts.y_token_vault.amount,
+                ctx.accounts.x_token_vault.amount,
+            ),
+        };
+
+        let pool = &ctx.accounts.pool;
+        let fee_bps = pool.current_fee_bps(Clock::get()?.slot);
+        let amount_received = amount_after_transfer_fee(&mint_in.to_account_info(), amount_in)?;
+
+        Ok(SwapQuote {
+            amount_out: torrent_math::swap_output(
+                amount_received,
+                reserve_in,
+                reserve_out,
+                fee_bps,
+            )
+            .unwrap(),
+            fee: torrent_math::swap_fee(amount_received, fee_bps).unwrap(),
+            fee_bps,
+        })
+    }
+
+    pub fn quote_add_liquidity(
+        ctx: Context<QuotePool>,
+        amount_x: u64,
+    ) -> Result<AddLiquidityQuote> {
+        let x_vault_balance = ctx.accounts.x_token_vault.amount;
+        let y_vault_balance = ctx.accounts.y_token_vault.amount;
+
+        let x_received =
+            amount_after_transfer_fee(&ctx.accounts.mint_x.to_account_info(), amount_x)?;
+        let amount_y =
+            torrent_math::deposit_amount(x_received, x_vault_balance, y_vault_balance).unwrap();
+        let y_received =
+            amount_after_transfer_fee(&ctx.accounts.mint_y.to_account_info(), amount_y)?;
+
+        Ok(AddLiquidityQuote {
+            amount_y,
+            liquidity: torrent_math::lp_tokens_for_deposit(
+                x_received,
+                y_received,
+                x_vault_balance,
+                y_vault_balance,
+                ctx.accounts.pool.pool_liquidity,
+            )
+            .unwrap(),
+        })
+    }
+
+    pub fn quote_remove_liquidity(
+        ctx: Context<QuotePool>,
+        lt_amount: u64,
+    ) -> Result<RemoveLiquidityQuote> {
+        let pool_liquidity = ctx.accounts.pool.pool_liquidity;
+        require!(pool_liquidity >= lt_amount, CustomError::ExcessiveBurn);
+
+        let share = |vault_balance: u64| {
+            torrent_math::withdraw_amount(lt_amount, vault_balance, pool_liquidity).unwrap()
+        };
+
+        Ok(RemoveLiquidityQuote {
+            amount_x: share(ctx.accounts.x_token_vault.amount),
+            amount_y: share(ctx.accounts.y_token_vault.amount),
+        })
+    }
+
+    pub fn register_referrer(ctx: Context<RegisterReferrer>, share_bps: u16) -> Result<()> {
+        require!(
+            share_bps <= ctx.accounts.torrent.max_referral_share_bps,
+            CustomError::InvalidReferralShare
+        );
+
+        let referrer = &mut ctx.accounts.referrer;
+        referrer.torrent = ctx.accounts.torrent.key();
+        referrer.owner = ctx.accounts.owner.key();
+        referrer.share_bps = share_bps;
+        referrer.swap_count = 0;
+        referrer.volume = 0;
+        referrer.fees_earned = 0;
+        referrer.bump = *ctx.bumps.get("referrer").unwrap();
+
+        Ok(())
+    }
+
+    pub fn open_trader_stats(ctx: Context<OpenTraderStats>) -> Result<()> {
+        let trader_stats = &mut ctx.accounts.trader_stats;
+        trader_stats.pool = ctx.accounts.pool.key();
+        trader_stats.trader = ctx.accounts.trader.key();
+        trader_stats.daily_volume = [0; VOLUME_WINDOW_DAYS];
+        trader_stats.last_day = 0;
+        trader_stats.bump = *ctx.bumps.get("trader_stats").unwrap();
+
+        Ok(())
+    }
+
+    pub fn create_farm(ctx: Context<CreateFarm>) -> Result<()> {
+        let torrent = &mut ctx.accounts.torrent;
+        let farm = &mut ctx.accounts.farm;
+        farm.torrent = torrent.key();
+        farm.id = torrent.farm_count;
+        farm.reward_streams = [RewardStream::default(); MAX_REWARD_STREAMS];
+        farm.stream_count = 0;
+        farm.total_staked = 0;
+        farm.bump = *ctx.bumps.get("farm").unwrap();
+        torrent.farm_count = torrent.farm_count.checked_add(1).unwrap();
+
+        Ok(())
+    }
+
+    pub fn add_reward_stream(
+        ctx: Context<AddRewardStream>,
+        emission_rate: u64,
+        start_timestamp: i64,
+        end_timestamp: i64,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            emission_rate > 0 && start_timestamp >= now && start_timestamp < end_timestamp,
+            CustomError::InvalidFarmSchedule
+        );
+        let total_rewards = emission_rate
+            .checked_mul(end_timestamp.checked_sub(start_timestamp).unwrap() as u64)
+            .unwrap();
+
+        let farm = &mut ctx.accounts.farm;
+        require!(
+            (farm.stream_count as usize) < MAX_REWARD_STREAMS,
+            CustomError::MaxRewardStreams
+        );
+        farm.update(now);
+        let index = farm.stream_count as usize;
+        farm.reward_streams[index] = RewardStream {
+            reward_mint: ctx.accounts.reward_mint.key(),
+            reward_vault: ctx.accounts.reward_vault.key(),
+            funder: ctx.accounts.funder.key(),
+            emission_rate,
+            start_timestamp,
+            end_timestamp,
+            last_update_timestamp: start_timestamp,
+            reward_per_share: 0,
+        };
+        farm.stream_count = farm.stream_count.checked_add(1).unwrap();
+
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.reward_token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.funder_reward_wallet.to_account_info(),
+                    mint: ctx.accounts.reward_mint.to_account_info(),
+                    to: ctx.accounts.reward_vault.to_account_info(),
+                    authority: ctx.accounts.funder.to_account_info(),
+                },
+            ),
+            total_rewards,
+            ctx.accounts.reward_mint.decimals,
+        )?;
+
+        // Every reward the schedule emits must be in the vault up front
+        ctx.accounts.reward_vault.reload()?;
+        require!(
+            ctx.accounts.reward_vault.amount >= total_rewards,
+            CustomError::InadequateBalance
+        );
+
+        Ok(())
+    }
+
+    pub fn stake_lp(ctx: Context<AlterStake>, amount: u64) -> Result<()> {
+        require!(
+            ctx.accounts.user_liquidity_token_wallet.amount >= amount,
+            CustomError::InadequateBalance
+        );
+
+        let farm = &mut ctx.accounts.farm;
+        farm.update(Clock::get()?.unix_timestamp);
+
+        let stake_account = &mut ctx.accounts.stake_account;
+        stake_account.farm = farm.key();
+        stake_account.owner = ctx.accounts.user.key();
+        stake_account.bump = *ctx.bumps.get("stake_account").unwrap();
+        stake_account.settle(farm);
+        stake_account.amount = stake_account.amount.checked_add(amount).unwrap();
+        stake_account.reset_reward_debt(farm);
+        farm.total_staked = farm.total_staked.checked_add(amount).unwrap();
+
+        anchor_spl::token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                anchor_spl::token::Transfer {
+                    from: ctx.accounts.user_liquidity_token_wallet.to_account_info(),
+                    to: ctx.accounts.lp_vault.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn unstake_lp(ctx: Context<AlterStake>, amount: u64) -> Result<()> {
+        require!(
+            ctx.accounts.stake_account.amount >= amount,
+            CustomError::InadequateBalance
+        );
+
+        let farm = &mut ctx.accounts.farm;
+        farm.update(Clock::get()?.unix_timestamp);
+
+        let stake_account = &mut ctx.accounts.stake_account;
+        stake_account.settle(farm);
+        stake_account.amount = stake_account.amount.checked_sub(amount).unwrap();
+        stake_account.reset_reward_debt(farm);
+        farm.total_staked = farm.total_staked.checked_sub(amount).unwrap();
+
+        let torrent_key = farm.torrent;
+        let farm_id = farm.id.to_le_bytes();
+        let farm_signature = &[
+            pda::FARM_SEED,
+            torrent_key.as_ref(),
+            farm_id.as_ref(),
+            &[farm.bump],
+        ];
+
+        anchor_spl::token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                anchor_spl::token::Transfer {
+                    from: ctx.accounts.lp_vault.to_account_info(),
+                    to: ctx.accounts.user_liquidity_token_wallet.to_account_info(),
+                    authority: ctx.accounts.farm.to_account_info(),
+                },
+            )
+            .with_signer(&[&farm_signature[..]]),
+            amount,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        farm.update(Clock::get()?.unix_timestamp);
+        let stream_index = farm.stream_index(&ctx.accounts.reward_mint.key())?;
+
+        let stake_account = &mut ctx.accounts.stake_account;
+        stake_account.settle(farm);
+        let rewards = stake_account.take_rewards(stream_index);
+
+        pay_reward(
+            &ctx.accounts.farm,
+            ctx.accounts.reward_mint.to_account_info(),
+            ctx.accounts.reward_vault.to_account_info(),
+            ctx.accounts.user_reward_wallet.to_account_info(),
+            ctx.accounts.reward_token_program.to_account_info(),
+            rewards,
+            ctx.accounts.reward_mint.decimals,
+        )
+    }
+
+    /// Claims every reward stream at once. Takes `[reward_mint, reward_vault,
+    /// user_reward_wallet, reward_token_program]` as remaining accounts for each stream,
+    /// in stream order.
+    pub fn claim_all_rewards<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClaimAllRewards<'info>>,
+    ) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        farm.update(Clock::get()?.unix_timestamp);
+        require!(
+            ctx.remaining_accounts.len() == 4 * farm.stream_count as usize,
+            CustomError::RewardStreamMismatch
+        );
+
+        let stake_account = &mut ctx.accounts.stake_account;
+        stake_account.settle(farm);
+
+        for (stream_index, accounts) in ctx.remaining_accounts.chunks(4).enumerate() {
+            let stream = ctx.accounts.farm.reward_streams[stream_index];
+            let (reward_mint, reward_vault, user_reward_wallet, reward_token_program) =
+                (&accounts[0], &accounts[1], &accounts[2], &accounts[3]);
+            require!(
+                reward_mint.key() == stream.reward_mint
+                    && reward_vault.key() == stream.reward_vault
+                    && reward_token_program.key() == *reward_mint.owner,
+                CustomError::RewardStreamMismatch
+            );
+
+            let wallet =
+                InterfaceAccount::<token_interface::TokenAccount>::try_from(user_reward_wallet)?;
+            require!(
+                wallet.owner == ctx.accounts.user.key() && wallet.mint == stream.reward_mint,
+                CustomError::RewardStreamMismatch
+            );
+            let decimals =
+                InterfaceAccount::<token_interface::Mint>::try_from(reward_mint)?.decimals;
+
+            let rewards = ctx.accounts.stake_account.take_rewards(stream_index);
+            pay_reward(
+                &ctx.accounts.farm,
+                reward_mint.clone(),
+                reward_vault.clone(),
+                user_reward_wallet.clone(),
+                reward_token_program.clone(),
+                rewards,
+                decimals,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Pays `amount` of a farm's rewards out of its vault for that reward mint.
+fn pay_reward<'info>(
+    farm: &Account<'info, Farm>,
+    reward_mint: AccountInfo<'info>,
+    reward_vault: AccountInfo<'info>,
+    user_reward_wallet: AccountInfo<'info>,
+    reward_token_program: AccountInfo<'info>,
+    amount: u64,
+    decimals: u8,
+) -> Result<()> {
+    let torrent_key = farm.torrent;
+    let farm_id = farm.id.to_le_bytes();
+    let farm_signature = &[
+        pda::FARM_SEED,
+        torrent_key.as_ref(),
+        farm_id.as_ref(),
+        &[farm.bump],
+    ];
+
+    token_interface::transfer_checked(
+        CpiContext::new(
+            reward_token_program,
+            TransferChecked {
+                from: reward_vault,
+                mint: reward_mint,
+                to: user_reward_wallet,
+                authority: farm.to_account_info(),
+            },
+        )
+        .with_signer(&[&farm_signature[..]]),
+        amount,
+        decimals,
+    )
+}
+
+/// Fails if `pool` is allow-listed and the user didn't pass their allowance.
+fn check_allowance(pool: &Pool, allowance: &Option<Account<Allowance>>) -> Result<()> {
+    require!(
+        !pool.allow_list || allowance.is_some(),
+        CustomError::NotAllowListed
+    );
+
+    Ok(())
+}
+
+/// Fails once the unix timestamp `deadline`, if one was given, has passed.
+fn check_deadline(deadline: Option<i64>) -> Result<()> {
+    if let Some(deadline) = deadline {
+        require!(
+            Clock::get()?.unix_timestamp <= deadline,
+            CustomError::Expired
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns the amount that reaches the destination of a transfer once any
+/// Token-2022 transfer fee configured on `mint` has been withheld.
+pub fn amount_after_transfer_fee(mint: &AccountInfo, amount: u64) -> Result<u64> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(amount);
+    }
+
+    let mint_data = mint.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+    let fee = match mint_state.get_extension::<TransferFeeConfig>() {
+        Ok(transfer_fee_config) => transfer_fee_config
+            .calculate_epoch_fee(Clock::get()?.epoch, amount)
+            .unwrap(),
+        Err(_) => 0,
+    };
+
+    Ok(amount.checked_sub(fee).unwrap())
+}
+
+pub fn is_native_mint(mint: &Pubkey) -> bool {
+    *mint == anchor_spl::token::spl_token::native_mint::ID
+        || *mint == spl_token_2022::native_mint::ID
+}
+
+/// Token account a user pays from or is paid into for one side of a pool
+struct UserWallet<'info> {
+    account: AccountInfo<'info>,
+    amount: u64,
+    // Wrapped SOL account created by the instruction, closed before it returns
+    temporary: bool,
+}
+
+/// Where a user without a token account for a native mint side is paid from or into
+struct NativeWallet<'a, 'info> {
+    account: &'a UncheckedAccount<'info>,
+    pool: Pubkey,
+    bump: u8,
+    system_program: &'a Program<'info, System>,
+}
+
+impl<'a, 'info> NativeWallet<'a, 'info> {
+    fn new(
+        account: &'a Option<UncheckedAccount<'info>>,
+        bumps: &BTreeMap<String, u8>,
+        pool: Pubkey,
+        system_program: &'a Program<'info, System>,
+    ) -> Option<Self> {
+        account.as_ref().map(|account| NativeWallet {
+            account,
+            pool,
+            bump: *bumps.get("native_wallet").unwrap(),
+            system_program,
+        })
+    }
+}
+
+/// The user's token account for a side, or for a native mint side the user didn't pass
+/// one for, a wrapped SOL account holding `deposit` lamports from the user.
+fn open_user_wallet<'info>(
+    wallet: &Option<Box<InterfaceAccount<'info, token_interface::TokenAccount>>>,
+    native_wallet: &Option<NativeWallet<'_, 'info>>,
+    user: &Signer<'info>,
+    mint: &InterfaceAccount<'info, token_interface::Mint>,
+    token_program: &Interface<'info, TokenInterface>,
+    deposit: u64,
+) -> Result<UserWallet<'info>> {
+    if let Some(wallet) = wallet {
+        return Ok(UserWallet {
+            account: wallet.to_account_info(),
+            amount: wallet.amount,
+            temporary: false,
+        });
+    }
+
+    require!(is_native_mint(&mint.key()), CustomError::MissingUserWallet);
+    let native_wallet = native_wallet
+        .as_ref()
+        .ok_or(CustomError::MissingUserWallet)?;
+    let account = native_wallet.account.to_account_info();
+    // Only one side of a pool can be paid through the native wallet
+    require_keys_eq!(*account.owner, System::id(), CustomError::NativeWalletInUse);
+
+    let user_key = user.key();
+    let native_wallet_signature = &[
+        pda::NATIVE_WALLET_SEED,
+        native_wallet.pool.as_ref(),
+        user_key.as_ref(),
+        &[native_wallet.bump],
+    ];
+    let space = anchor_spl::token::spl_token::state::Account::LEN;
+    // Lamports above the rent exempt reserve are the account's balance once initialised
+    let lamports = Rent::get()?
+        .minimum_balance(space)
+        .checked_add(deposit)
+        .unwrap();
+    let system_program = native_wallet.system_program.to_account_info();
+
+    // Anyone can send lamports to the address, so it's funded, allocated and assigned
+    // separately when it already has some.
+    let current_lamports = account.lamports();
+    if current_lamports == 0 {
+        anchor_lang::system_program::create_account(
+            CpiContext::new(
+                system_program.clone(),
+                anchor_lang::system_program::CreateAccount {
+                    from: user.to_account_info(),
+                    to: account.clone(),
+                },
+            )
+            .with_signer(&[&native_wallet_signature[..]]),
+            lamports,
+            space as u64,
+            &token_program.key(),
+        )?;
+    } else {
+        let top_up = lamports.saturating_sub(current_lamports);
+        if top_up > 0 {
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    system_program.clone(),
+                    anchor_lang::system_program::Transfer {
+                        from: user.to_account_info(),
+                        to: account.clone(),
+                    },
+                ),
+                top_up,
+            )?;
+        }
+        anchor_lang::system_program::allocate(
+            CpiContext::new(
+                system_program.clone(),
+                anchor_lang::system_program::Allocate {
+                    account_to_allocate: account.clone(),
+                },
+            )
+            .with_signer(&[&native_wallet_signature[..]]),
+            space as u64,
+        )?;
+        anchor_lang::system_program::assign(
+            CpiContext::new(
+                system_program,
+                anchor_lang::system_program::Assign {
+                    account_to_assign: account.clone(),
+                },
+            )
+            .with_signer(&[&native_wallet_signature[..]]),
+            &token_program.key(),
+        )?;
+    }
+
+    token_interface::initialize_account3(CpiContext::new(
+        token_program.to_account_info(),
+        InitializeAccount3 {
+            account: account.clone(),
+            mint: mint.to_account_info(),
+            authority: user.to_account_info(),
+        },
+    ))?;
+
+    Ok(UserWallet {
+        account,
+        amount: deposit,
+        temporary: true,
+    })
+}
+
+/// Closes a temporary wrapped SOL account, returning its lamports to the user. The
+/// user's own token accounts are left open.
+fn close_user_wallet<'info>(
+    wallet: &UserWallet<'info>,
+    user: &Signer<'info>,
+    token_program: &Interface<'info, TokenInterface>,
+) -> Result<()> {
+    if !wallet.temporary {
+        return Ok(());
+    }
+
+    token_interface::close_account(CpiContext::new(
+        token_program.to_account_info(),
+        CloseAccount {
+            account: wallet.account.clone(),
+            destination: user.to_account_info(),
+            authority: user.to_account_info(),
+        },
+    ))
+}
+
+#[derive(Accounts)]
+#[instruction(decimals: u8)]
+pub struct InitializeTorrent<'info> {
+    #[account(mut)]
+    authority: Signer<'info>,
+
+    /// Stores torrent state
+    #[account(
+        init,
+        seeds = [pda::TORRENT_SEED, authority.key().as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + Torrent::SIZE
+    )]
+    torrent: Account<'info, Torrent>,
+
+    /// This is the liquidity token for torrent's pools
+    #[account(
+        init,
+        seeds = [pda::LIQUIDITY_TOKEN_SEED, torrent.key().as_ref()],
+        bump,
+        payer = authority,
+        mint::decimals = decimals,
+        mint::authority = torrent,
+    )]
+    liquidity_token: Account<'info, Mint>,
+
+    system_program: Program<'info, System>,
+    token_program: Program<'info, Token>,
+    rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateTorrent<'info> {
+    admin: Signer<'info>,
+
+    #[account(mut, constraint = torrent.roles.admin == admin.key() @ CustomError::Unauthorized)]
+    torrent: Account<'info, Torrent>,
+}
+
+#[derive(Accounts)]
+pub struct ManageFeeTiers<'info> {
+    fee_manager: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = torrent.roles.fee_manager == fee_manager.key() @ CustomError::Unauthorized
+    )]
+    torrent: Account<'info, Torrent>,
+}
+
+#[derive(Accounts)]
+pub struct SetPaused<'info> {
+    pauser: Signer<'info>,
+
+    #[account(mut, constraint = torrent.roles.pauser == pauser.key() @ CustomError::Unauthorized)]
+    torrent: Account<'info, Torrent>,
+}
+
+#[derive(Accounts)]
+#[instruction(initial_x: u64, initial_y: u64, fee_tier: u16)]
+pub struct InitializePool<'info> {
+    #[account(
+        mut, has_one = liquidity_token_mint,
+        constraint = torrent.pool_creation.permissionless
+            || torrent.roles.pool_creator == creator.key() @ CustomError::Unauthorized
+    )]
+    torrent: Box<Account<'info, Torrent>>,
+    #[account(mut)]
+    liquidity_token_mint: Box<Account<'info, Mint>>,
+
+    #[account(mut)]
+    creator: Signer<'info>,
+
+    /// Token pair for this pool
+    #[account(constraint = *mint_x.to_account_info().owner == token_x_program.key())]
+    mint_x: Box<InterfaceAccount<'info, token_interface::Mint>>,
+    #[account(constraint = *mint_y.to_account_info().owner == token_y_program.key())]
+    mint_y: Box<InterfaceAccount<'info, token_interface::Mint>>,
+
+    /// Creator's token accounts
+    #[account(
+        mut,
+        constraint = creator_x_wallet.owner == creator.key(),
+        constraint = creator_x_wallet.mint == mint_x.key(),
+        constraint = creator_x_wallet.amount >= initial_x @CustomError::InadequateBalance
+    )]
+    creator_x_wallet: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    #[account(
+        mut,
+        constraint = creator_y_wallet.owner == creator.key(),
+        constraint = creator_y_wallet.mint == mint_y.key(),
+        constraint = creator_y_wallet.amount >= initial_y @ CustomError::InadequateBalance
+    )]
+    creator_y_wallet: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    #[account(
+        mut,
+        constraint = creator_liquidity_token_wallet.owner == creator.key(),
+        constraint = creator_liquidity_token_wallet.mint == liquidity_token_mint.key()
+    )]
+    creator_liquidity_token_wallet: Box<Account<'info, TokenAccount>>,
+
+    /// Stores pool state
+    #[account(
+        init,
+        seeds = [
+            torrent.key().as_ref(),
+            mint_x.key().as_ref(),
+            mint_y.key().as_ref(),
+            fee_tier.to_le_bytes().as_ref(),
+        ],
+        bump,
+        payer = creator,
+        space = 8 + Pool::SIZE,
+    )]
+    pool: Box<Account<'info, Pool>>,
+
+    /// Vault for storing x_tokens
+    #[account(
+        init,
+        seeds = [pda::X_VAULT_SEED, pool.key().as_ref()],
+        bump,
+        payer = creator,
+        token::mint = mint_x,
+        token::authority = pool,
+        token::token_program = token_x_program,
+    )]
+    x_token_vault: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// Vault for storing y_tokens
+    #[account(
+        init,
+        seeds = [pda::Y_VAULT_SEED, pool.key().as_ref()],
+        bump,
+        payer = creator,
+        token::mint = mint_y,
+        token::authority = pool,
+        token::token_program = token_y_program,
+    )]
+    y_token_vault: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// Receives the pool creation fee
+    /// CHECK: only ever credited, and pinned to the torrent's treasury
+    #[account(mut, address = torrent.pool_creation.treasury)]
+    treasury: UncheckedAccount<'info>,
+
+    /// Accounts for creation fees paid in tokens
+    #[account(address = torrent.pool_creation.fee_mint)]
+    fee_mint: Option<Box<InterfaceAccount<'info, token_interface::Mint>>>,
+    #[account(mut, constraint = creator_fee_wallet.owner == creator.key())]
+    creator_fee_wallet: Option<Box<InterfaceAccount<'info, token_interface::TokenAccount>>>,
+    #[account(
+        mut,
+        constraint = treasury_fee_wallet.owner == torrent.pool_creation.treasury,
+        constraint = treasury_fee_wallet.mint == torrent.pool_creation.fee_mint,
+    )]
+    treasury_fee_wallet: Option<Box<InterfaceAccount<'info, token_interface::TokenAccount>>>,
+    fee_token_program: Option<Interface<'info, TokenInterface>>,
+
+    /// System accounts
+    system_program: Program<'info, System>,
+    token_program: Program<'info, Token>,
+    token_x_program: Interface<'info, TokenInterface>,
+    token_y_program: Interface<'info, TokenInterface>,
+    rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(change: ParameterChange)]
+pub struct QueueChange<'info> {
+    #[account(mut)]
+    proposer: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = torrent.roles.admin == proposer.key()
+            || torrent.roles.get(change.role()) == proposer.key() @ CustomError::Unauthorized
+    )]
+    torrent: Account<'info, Torrent>,
+
+    /// Stores the change until its timelock elapses
+    #[account(
+        init,
+        seeds = [pda::PENDING_CHANGE_SEED, torrent.key().as_ref(), torrent.change_count.to_le_bytes().as_ref()],
+        bump,
+        payer = proposer,
+        space = 8 + PendingChange::SIZE,
+    )]
+    pending_change: Account<'info, PendingChange>,
+
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteChange<'info> {
+    /// Refunded the pending change's rent
+    #[account(mut)]
+    proposer: SystemAccount<'info>,
+
+    #[account(mut)]
+    torrent: Box<Account<'info, Torrent>>,
+    #[account(mut, has_one = torrent, has_one = proposer, close = proposer)]
+    pending_change: Account<'info, PendingChange>,
+
+    /// Pool targeted by pool-level changes
+    #[account(mut, has_one = torrent)]
+    pool: Option<Box<Account<'info, Pool>>>,
+}
+
+#[derive(Accounts)]
+pub struct CancelChange<'info> {
+    canceller: Signer<'info>,
+
+    /// Refunded the pending change's rent
+    #[account(mut)]
+    proposer: SystemAccount<'info>,
+
+    #[account(
+        constraint = torrent.roles.admin == canceller.key()
+            || torrent.roles.get(pending_change.change.role()) == canceller.key() @ CustomError::Unauthorized
+    )]
+    torrent: Account<'info, Torrent>,
+    #[account(mut, has_one = torrent, has_one = proposer, close = proposer)]
+    pending_change: Account<'info, PendingChange>,
+}
+
+#[derive(Accounts)]
+pub struct DelistPool<'info> {
+    admin: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = torrent.roles.admin == admin.key() @ CustomError::Unauthorized,
+        constraint = torrent.pools[pool.index as usize] == pool.key() @ CustomError::TorrentPoolMismatch
+    )]
+    torrent: Account<'info, Torrent>,
+    #[account(mut, has_one = torrent)]
+    pool: Account<'info, Pool>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePool<'info> {
+    admin: Signer<'info>,
+
+    #[account(constraint = torrent.roles.admin == admin.key() @ CustomError::Unauthorized)]
+    torrent: Account<'info, Torrent>,
+    #[account(mut, has_one = torrent)]
+    pool: Account<'info, Pool>,
+}
+
+#[derive(Accounts)]
+pub struct IssueAllowance<'info> {
+    #[account(mut)]
+    gatekeeper: Signer<'info>,
+
+    #[account(
+        constraint = torrent.roles.gatekeeper == gatekeeper.key() @ CustomError::Unauthorized
+    )]
+    torrent: Account<'info, Torrent>,
+
+    /// CHECK: only used as a seed, any key can be allowed
+    user: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        seeds = [pda::ALLOWANCE_SEED, torrent.key().as_ref(), user.key().as_ref()],
+        bump,
+        payer = gatekeeper,
+        space = 8 + Allowance::SIZE,
+    )]
+    allowance: Account<'info, Allowance>,
+
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeAllowance<'info> {
+    #[account(mut)]
+    gatekeeper: Signer<'info>,
+
+    #[account(
+        constraint = torrent.roles.gatekeeper == gatekeeper.key() @ CustomError::Unauthorized
+    )]
+    torrent: Account<'info, Torrent>,
+    #[account(mut, has_one = torrent, close = gatekeeper)]
+    allowance: Account<'info, Allowance>,
+}
+
+#[derive(Accounts)]
+pub struct AlterLiquidity<'info> {
+    #[account(mut)]
+    user: Signer<'info>,
+
+    #[account(
+        mut, has_one = liquidity_token_mint,
+        constraint = pool.delisted
+            || torrent.pools[pool.index as usize] == pool.key() @ CustomError::TorrentPoolMismatch
+    )]
+    torrent: Box<Account<'info, Torrent>>,
+    #[account(
+        mut,
+        has_one = torrent,
+        seeds = [
+            torrent.key().as_ref(),
+            x_token_vault.mint.as_ref(),
+            y_token_vault.mint.as_ref(),
+            pool.fee_tier.to_le_bytes().as_ref(),
+        ],
+        bump
+    )]
+    pool: Box<Account<'info, Pool>>,
+
+    /// Proof the user may use allow-listed pools
+    #[account(
+        seeds = [pda::ALLOWANCE_SEED, torrent.key().as_ref(), user.key().as_ref()],
+        bump = allowance.bump,
+    )]
+    allowance: Option<Account<'info, Allowance>>,
+
+    #[account(mut, seeds = [pda::X_VAULT_SEED, pool.key().as_ref()], bump)]
+    x_token_vault: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    #[account(mut, seeds = [pda::Y_VAULT_SEED, pool.key().as_ref()], bump)]
+    y_token_vault: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// Token pair for this pool
+    #[account(
+        address = x_token_vault.mint,
+        constraint = *mint_x.to_account_info().owner == token_x_program.key(),
+    )]
+    mint_x: Box<InterfaceAccount<'info, token_interface::Mint>>,
+    #[account(
+        address = y_token_vault.mint,
+        constraint = *mint_y.to_account_info().owner == token_y_program.key(),
+    )]
+    mint_y: Box<InterfaceAccount<'info, token_interface::Mint>>,
+
+    #[account(mut)]
+    liquidity_token_mint: Box<Account<'info, Mint>>,
+
+    /// User's token accounts
+    #[account(
+        mut,
+        constraint = user_x_wallet.owner == user.key(),
+        constraint = user_x_wallet.mint == x_token_vault.mint,
+    )]
+    user_x_wallet: Option<Box<InterfaceAccount<'info, token_interface::TokenAccount>>>,
+    #[account(
+        mut,
+        constraint = user_y_wallet.owner == user.key(),
+        constraint = user_y_wallet.mint == y_token_vault.mint,
+    )]
+    user_y_wallet: Option<Box<InterfaceAccount<'info, token_interface::TokenAccount>>>,
+
+    /// CHECK: created as a wrapped SOL account and closed within the instruction, in place
+    /// of a user wallet left out for a native mint side
+    #[account(
+        mut,
+        seeds = [pda::NATIVE_WALLET_SEED, pool.key().as_ref(), user.key().as_ref()],
+        bump,
+    )]
+    native_wallet: Option<UncheckedAccount<'info>>,
+    #[account(
+        mut,
+        constraint = user_liquidity_token_wallet.owner == user.key(),
+        constraint = user_liquidity_token_wallet.mint == liquidity_token_mint.key(),
+    )]
+    user_liquidity_token_wallet: Box<Account<'info, TokenAccount>>,
+
+    system_program: Program<'info, System>,
+    token_program: Program<'info, Token>,
+    token_x_program: Interface<'info, TokenInterface>,
+    token_y_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct OpenPosition<'info> {
+    #[account(mut)]
+    user: Signer<'info>,
+
+    #[account(
+        constraint = torrent.pools[pool.index as usize] == pool.key() @ CustomError::TorrentPoolMismatch
+    )]
+    torrent: Box<Account<'info, Torrent>>,
+    #[account(
+        mut,
+        has_one = torrent,
+        seeds = [
+            torrent.key().as_ref(),
+            x_token_vault.mint.as_ref(),
+            y_token_vault.mint.as_ref(),
+            pool.fee_tier.to_le_bytes().as_ref(),
+        ],
+        bump
+    )]
+    pool: Box<Account<'info, Pool>>,
+
+    /// Proof the user may use allow-listed pools
+    #[account(
+        seeds = [pda::ALLOWANCE_SEED, torrent.key().as_ref(), user.key().as_ref()],
+        bump = allowance.bump,
+    )]
+    allowance: Option<Account<'info, Allowance>>,
+
+    #[account(mut, seeds = [pda::X_VAULT_SEED, pool.key().as_ref()], bump)]
+    x_token_vault: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    #[account(mut, seeds = [pda::Y_VAULT_SEED, pool.key().as_ref()], bump)]
+    y_token_vault: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// Token pair for this pool
+    #[account(
+        address = x_token_vault.mint,
+        constraint = *mint_x.to_account_info().owner == token_x_program.key(),
+    )]
+    mint_x: Box<InterfaceAccount<'info, token_interface::Mint>>,
+    #[account(
+        address = y_token_vault.mint,
+        constraint = *mint_y.to_account_info().owner == token_y_program.key(),
+    )]
+    mint_y: Box<InterfaceAccount<'info, token_interface::Mint>>,
+
+    /// 1-of-1 mint representing ownership of the position
+    #[account(
+        init,
+        payer = user,
+        mint::decimals = 0,
+        mint::authority = torrent,
+    )]
+    position_mint: Box<Account<'info, Mint>>,
+
+    /// Stores position state
+    #[account(
+        init,
+        seeds = [pda::POSITION_SEED, position_mint.key().as_ref()],
+        bump,
+        payer = user,
+        space = 8 + Position::SIZE,
+    )]
+    position: Box<Account<'info, Position>>,
+
+    /// User's token accounts
+    #[account(
+        init,
+        payer = user,
+        associated_token::mint = position_mint,
+        associated_token::authority = user,
+    )]
+    user_position_token_wallet: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = user_x_wallet.owner == user.key(),
+        constraint = user_x_wallet.mint == x_token_vault.mint,
+    )]
+    user_x_wallet: Option<Box<InterfaceAccount<'info, token_interface::TokenAccount>>>,
+    #[account(
+        mut,
+        constraint = user_y_wallet.owner == user.key(),
+        constraint = user_y_wallet.mint == y_token_vault.mint,
+    )]
+    user_y_wallet: Option<Box<InterfaceAccount<'info, token_interface::TokenAccount>>>,
+
+    /// CHECK: created as a wrapped SOL account and closed within the instruction, in place
+    /// of a user wallet left out for a native mint side
+    #[account(
+        mut,
+        seeds = [pda::NATIVE_WALLET_SEED, pool.key().as_ref(), user.key().as_ref()],
+        bump,
+    )]
+    native_wallet: Option<UncheckedAccount<'info>>,
+
+    /// System accounts
+    system_program: Program<'info, System>,
+    token_program: Program<'info, Token>,
+    token_x_program: Interface<'info, TokenInterface>,
+    token_y_program: Interface<'info, TokenInterface>,
+    associated_token_program: Program<'info, AssociatedToken>,
+    rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct AlterPositionLiquidity<'info> {
+    #[account(mut)]
+    user: Signer<'info>,
+
+    #[account(
+        constraint = pool.delisted
+            || torrent.pools[pool.index as usize] == pool.key() @ CustomError::TorrentPoolMismatch
+    )]
+    torrent: Box<Account<'info, Torrent>>,
+    #[account(
+        mut,
+        has_one = torrent,
+        seeds = [
+            torrent.key().as_ref(),
+            x_token_vault.mint.as_ref(),
+            y_token_vault.mint.as_ref(),
+            pool.fee_tier.to_le_bytes().as_ref(),
+        ],
+        bump
+    )]
+    pool: Box<Account<'info, Pool>>,
+
+    /// Proof the user may use allow-listed pools
+    #[account(
+        seeds = [pda::ALLOWANCE_SEED, torrent.key().as_ref(), user.key().as_ref()],
+        bump = allowance.bump,
+    )]
+    allowance: Option<Account<'info, Allowance>>,
+
+    #[account(mut, seeds = [pda::X_VAULT_SEED, pool.key().as_ref()], bump)]
+    x_token_vault: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    #[account(mut, seeds = [pda::Y_VAULT_SEED, pool.key().as_ref()], bump)]
+    y_token_vault: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// Token pair for this pool
+    #[account(
+        address = x_token_vault.mint,
+        constraint = *mint_x.to_account_info().owner == token_x_program.key(),
+    )]
+    mint_x: Box<InterfaceAccount<'info, token_interface::Mint>>,
+    #[account(
+        address = y_token_vault.mint,
+        constraint = *mint_y.to_account_info().owner == token_y_program.key(),
+    )]
+    mint_y: Box<InterfaceAccount<'info, token_interface::Mint>>,
+
+    #[account(
+        mut,
+        has_one = pool,
+        has_one = position_mint,
+        seeds = [pda::POSITION_SEED, position_mint.key().as_ref()],
+        bump = position.bump,
+    )]
+    position: Box<Account<'info, Position>>,
+    position_mint: Box<Account<'info, Mint>>,
+
+    /// Holding the position token authorises changes to the position
+    #[account(
+        constraint = user_position_token_wallet.owner == user.key(),
+        constraint = user_position_token_wallet.mint == position_mint.key(),
+        constraint = user_position_token_wallet.amount == 1 @ CustomError::PositionNotHeld,
+    )]
+    user_position_token_wallet: Box<Account<'info, TokenAccount>>,
+
+    /// User's token accounts
+    #[account(
+        mut,
+        constraint = user_x_wallet.owner == user.key(),
+        constraint = user_x_wallet.mint == x_token_vault.mint,
+    )]
+    user_x_wallet: Option<Box<InterfaceAccount<'info, token_interface::TokenAccount>>>,
+    #[account(
+        mut,
+        constraint = user_y_wallet.owner == user.key(),
+        constraint = user_y_wallet.mint == y_token_vault.mint,
+    )]
+    user_y_wallet: Option<Box<InterfaceAccount<'info, token_interface::TokenAccount>>>,
+
+    /// CHECK: created as a wrapped SOL account and closed within the instruction, in place
+    /// of a user wallet left out for a native mint side
+    #[account(
+        mut,
+        seeds = [pda::NATIVE_WALLET_SEED, pool.key().as_ref(), user.key().as_ref()],
+        bump,
+    )]
+    native_wallet: Option<UncheckedAccount<'info>>,
+
+    system_program: Program<'info, System>,
+    token_x_program: Interface<'info, TokenInterface>,
+    token_y_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct Swap<'info> {
+    #[account(mut)]
+    user: Signer<'info>,
+
+    #[account(
+        constraint = torrent.pools[pool.index as usize] == pool.key() @ CustomError::TorrentPoolMismatch
+    )]
+    torrent: Box<Account<'info, Torrent>>,
+    #[account(
+        mut,
+        has_one = torrent,
+        seeds = [
+            torrent.key().as_ref(),
+            x_token_vault.mint.as_ref(),
+            y_token_vault.mint.as_ref(),
+            pool.fee_tier.to_le_bytes().as_ref(),
+        ],
+        bump
+    )]
+    pool: Box<Account<'info, Pool>>,
+
+    /// Proof the user may use allow-listed pools
+    #[account(
+        seeds = [pda::ALLOWANCE_SEED, torrent.key().as_ref(), user.key().as_ref()],
+        bump = allowance.bump,
+    )]
+    allowance: Option<Account<'info, Allowance>>,
+
+    #[account(mut, seeds = [pda::X_VAULT_SEED, pool.key().as_ref()], bump)]
+    x_token_vault: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    #[account(mut, seeds = [pda::Y_VAULT_SEED, pool.key().as_ref()], bump)]
+    y_token_vault: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// Token pair for this pool
+    #[account(
+        address = x_token_vault.mint,
+        constraint = *mint_x.to_account_info().owner == token_x_program.key(),
+    )]
+    mint_x: Box<InterfaceAccount<'info, token_interface::Mint>>,
+    #[account(
+        address = y_token_vault.mint,
+        constraint = *mint_y.to_account_info().owner == token_y_program.key(),
+    )]
+    mint_y: Box<InterfaceAccount<'info, token_interface::Mint>>,
+
+    /// User's token accounts
+    #[account(
+        mut,
+        constraint = user_x_wallet.owner == user.key(),
+        constraint = user_x_wallet.mint == x_token_vault.mint,
+    )]
+    user_x_wallet: Option<Box<InterfaceAccount<'info, token_interface::TokenAccount>>>,
+    #[account(
+        mut,
+        constraint = user_y_wallet.owner == user.key(),
+        constraint = user_y_wallet.mint == y_token_vault.mint,
+    )]
+    user_y_wallet: Option<Box<InterfaceAccount<'info, token_interface::TokenAccount>>>,
+
+    /// CHECK: created as a wrapped SOL account and closed within the instruction, in place
+    /// of a user wallet left out for a native mint side
+    #[account(
+        mut,
+        seeds = [pda::NATIVE_WALLET_SEED, pool.key().as_ref(), user.key().as_ref()],
+        bump,
+    )]
+    native_wallet: Option<UncheckedAccount<'info>>,
+
+    /// Integrator credited with part of the swap fee, and its wallet for the input token
+    #[account(mut, has_one = torrent)]
+    referrer: Option<Box<Account<'info, Referrer>>>,
+    #[account(mut)]
+    referrer_wallet: Option<Box<InterfaceAccount<'info, token_interface::TokenAccount>>>,
+
+    /// Trader's volume, used for fee discounts and updated by the swap
+    #[account(
+        mut,
+        seeds = [pda::TRADER_STATS_SEED, pool.key().as_ref(), user.key().as_ref()],
+        bump = trader_stats.bump,
+    )]
+    trader_stats: Option<Box<Account<'info, TraderStats>>>,
+
+    system_program: Program<'info, System>,
+    token_x_program: Interface<'info, TokenInterface>,
+    token_y_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct QuotePool<'info> {
+    pool: Box<Account<'info, Pool>>,
+
+    #[account(seeds = [pda::X_VAULT_SEED, pool.key().as_ref()], bump)]
+    x_token_vault: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    #[account(seeds = [pda::Y_VAULT_SEED, pool.key().as_ref()], bump)]
+    y_token_vault: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// Token pair for this pool
+    #[account(address = x_token_vault.mint)]
+    mint_x: Box<InterfaceAccount<'info, token_interface::Mint>>,
+    #[account(address = y_token_vault.mint)]
+    mint_y: Box<InterfaceAccount<'info, token_interface::Mint>>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterReferrer<'info> {
+    #[account(mut)]
+    owner: Signer<'info>,
+
+    torrent: Box<Account<'info, Torrent>>,
+
+    #[account(
+        init,
+        seeds = [pda::REFERRER_SEED, torrent.key().as_ref(), owner.key().as_ref()],
+        bump,
+        payer = owner,
+        space = 8 + Referrer::SIZE,
+    )]
+    referrer: Account<'info, Referrer>,
+
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OpenTraderStats<'info> {
+    #[account(mut)]
+    trader: Signer<'info>,
+
+    pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        init,
+        seeds = [pda::TRADER_STATS_SEED, pool.key().as_ref(), trader.key().as_ref()],
+        bump,
+        payer = trader,
+        space = 8 + TraderStats::SIZE,
+    )]
+    trader_stats: Account<'info, TraderStats>,
+
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreateFarm<'info> {
+    #[account(mut)]
+    admin: Signer<'info>,
+
+    #[account(
+        mut, has_one = liquidity_token_mint,
+        constraint = torrent.roles.admin == admin.key() @ CustomError::Unauthorized
+    )]
+    torrent: Box<Account<'info, Torrent>>,
+    liquidity_token_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        seeds = [pda::FARM_SEED, torrent.key().as_ref(), torrent.farm_count.to_le_bytes().as_ref()],
+        bump,
+        payer = admin,
+        space = 8 + Farm::SIZE,
+    )]
+    farm: Box<Account<'info, Farm>>,
+
+    /// Holds staked liquidity tokens
+    #[account(
+        init,
+        seeds = [pda::FARM_LP_VAULT_SEED, farm.key().as_ref()],
+        bump,
+        payer = admin,
+        token::mint = liquidity_token_mint,
+        token::authority = farm,
+    )]
+    lp_vault: Box<Account<'info, TokenAccount>>,
+
+    system_program: Program<'info, System>,
+    token_program: Program<'info, Token>,
+    rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct AddRewardStream<'info> {
+    admin: Signer<'info>,
+    /// Pays for the stream's whole schedule up front
+    #[account(mut)]
+    funder: Signer<'info>,
+
+    #[account(constraint = torrent.roles.admin == admin.key() @ CustomError::Unauthorized)]
+    torrent: Box<Account<'info, Torrent>>,
+    #[account(mut, has_one = torrent)]
+    farm: Box<Account<'info, Farm>>,
+
+    #[account(constraint = *reward_mint.to_account_info().owner == reward_token_program.key())]
+    reward_mint: Box<InterfaceAccount<'info, token_interface::Mint>>,
+    /// Holds the stream's rewards until they're claimed
+    #[account(
+        init,
+        seeds = [pda::FARM_REWARD_VAULT_SEED, farm.key().as_ref(), reward_mint.key().as_ref()],
+        bump,
+        payer = funder,
+        token::mint = reward_mint,
+        token::authority = farm,
+        token::token_program = reward_token_program,
+    )]
+    reward_vault: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    #[account(
+        mut,
+        constraint = funder_reward_wallet.owner == funder.key(),
+        constraint = funder_reward_wallet.mint == reward_mint.key(),
+    )]
+    funder_reward_wallet: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    system_program: Program<'info, System>,
+    reward_token_program: Interface<'info, TokenInterface>,
+    rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct AlterStake<'info> {
+    #[account(mut)]
+    user: Signer<'info>,
+
+    #[account(mut)]
+    farm: Box<Account<'info, Farm>>,
+    #[account(
+        init_if_needed,
+        seeds = [pda::STAKE_SEED, farm.key().as_ref(), user.key().as_ref()],
+        bump,
+        payer = user,
+        space = 8 + StakeAccount::SIZE,
+    )]
+    stake_account: Box<Account<'info, StakeAccount>>,
+
+    #[account(mut, seeds = [pda::FARM_LP_VAULT_SEED, farm.key().as_ref()], bump)]
+    lp_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = user_liquidity_token_wallet.owner == user.key(),
+        constraint = user_liquidity_token_wallet.mint == lp_vault.mint,
+    )]
+    user_liquidity_token_wallet: Box<Account<'info, TokenAccount>>,
+
+    system_program: Program<'info, System>,
+    token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRewards<'info> {
+    user: Signer<'info>,
+
+    #[account(mut)]
+    farm: Box<Account<'info, Farm>>,
+    #[account(
+        mut,
+        seeds = [pda::STAKE_SEED, farm.key().as_ref(), user.key().as_ref()],
+        bump = stake_account.bump,
+    )]
+    stake_account: Box<Account<'info, StakeAccount>>,
+
+    #[account(constraint = *reward_mint.to_account_info().owner == reward_token_program.key())]
+    reward_mint: Box<InterfaceAccount<'info, token_interface::Mint>>,
+    #[account(
+        mut,
+        seeds = [pda::FARM_REWARD_VAULT_SEED, farm.key().as_ref(), reward_mint.key().as_ref()],
+        bump,
+    )]
+    reward_vault: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    #[account(
+        mut,
+        constraint = user_reward_wallet.owner == user.key(),
+        constraint = user_reward_wallet.mint == reward_mint.key(),
+    )]
+    user_reward_wallet: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    reward_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimAllRewards<'info> {
+    user: Signer<'info>,
+
+    #[account(mut)]
+    farm: Box<Account<'info, Farm>>,
+    #[account(
+        mut,
+        seeds = [pda::STAKE_SEED, farm.key().as_ref(), user.key().as_ref()],
+        bump = stake_account.bump,
+    )]
+    stake_account: Box<Account<'info, StakeAccount>>,
+}
+
+#[account]
+pub struct Torrent {
+    // Key the torrent address is derived from
+    pub authority: Pubkey,
+
+    // Keys allowed to administer the torrent
+    pub roles: Roles,
+
+    // Co-signers required on sensitive admin instructions, off while the threshold is zero
+    pub multisig: Multisig,
+
+    // Liquidity token mint
+    pub liquidity_token_mint: Pubkey,
+
+    // Total supply of liquidity tokens
+    pub torrent_liquidity: u64,
+
+    // Associated Pools
+    pub pools: [Pubkey; MAX_POOLS],
+
+    pub bump: u8,
+
+    // Mint properties accepted when creating pools
+    pub mint_policy: MintPolicy,
+
+    // Who may create pools and what they pay for it
+    pub pool_creation: PoolCreationConfig,
+
+    // Swap fees, in basis points, that pools can be created with. Zero marks a free slot
+    pub fee_tiers: [u16; MAX_FEE_TIERS],
+
+    // Stops swaps and deposits in every pool
+    pub paused: bool,
+
+    // Seconds a queued parameter change waits before it can be executed
+    pub timelock_delay: i64,
+
+    // Number of parameter changes queued so far, used to address them
+    pub change_count: u64,
+
+    // Number of farms created so far, used to address them
+    pub farm_count: u64,
+
+    // Most of a swap fee, in basis points of it, that a referrer can take
+    pub max_referral_share_bps: u16,
+
+    // Swap fee discounts for traders by rolling volume
+    pub volume_tiers: [VolumeTier; MAX_VOLUME_TIERS],
+
+    // Mint trader volume is counted in. Only swaps into or out of it count, in pools
+    // holding it, and volume in one pool doesn't discount swaps in another
+    pub volume_quote_mint: Pubkey,
+}
+
+impl Torrent {
+    const SIZE: usize = 32
+        + Roles::SIZE
+        + Multisig::SIZE
+        + 32
+        + 8
+        + (32 * 10)
+        + 1
+        + MintPolicy::SIZE
+        + PoolCreationConfig::SIZE
+        + (2 * MAX_FEE_TIERS)
+        + 1
+        + 8
+        + 8
+        + 8
+        + 2
+        + (VolumeTier::SIZE * MAX_VOLUME_TIERS)
+        + 32;
+
+    pub fn register_pool(&mut self, new_pool: Pubkey) -> Result<u8> {
+        for (index, pool) in self.pools.iter_mut().enumerate() {
+            if *pool != Pubkey::default() {
+                continue;
+            }
+
+            *pool = new_pool;
+
+            return Ok(index as u8);
+        }
+        Err(error!(CustomError::MaxPoolLimit))
+    }
+
+    pub fn add_fee_tier(&mut self, fee_bps: u16) -> Result<()> {
+        require!(
+            fee_bps != 0 && (fee_bps as u64) < BPS_DENOMINATOR,
+            CustomError::InvalidFee
+        );
+        require!(
+            !self.fee_tiers.contains(&fee_bps),
+            CustomError::DuplicateFeeTier
+        );
+
+        match self.fee_tiers.iter_mut().find(|tier| **tier == 0) {
+            Some(tier) => {
+                *tier = fee_bps;
+                Ok(())
+            }
+            None => Err(error!(CustomError::MaxFeeTierLimit)),
+        }
+    }
+
+    pub fn remove_fee_tier(&mut self, fee_bps: u16) -> Result<()> {
+        match self.fee_tiers.iter_mut().find(|tier| **tier == fee_bps) {
+            Some(tier) if fee_bps != 0 => {
+                *tier = 0;
+                Ok(())
+            }
+            _ => Err(error!(CustomError::UnknownFeeTier)),
+        }
+    }
+
+    /// Applies the largest volume tier discount `volume` qualifies for to `fee_bps`.
+    pub fn discounted_fee_bps(&self, fee_bps: u16, volume: u128) -> u16 {
+        let discount_bps = self
+            .volume_tiers
+            .iter()
+            .filter(|tier| tier.discount_bps > 0 && volume >= tier.min_volume as u128)
+            .map(|tier| tier.discount_bps)
+            .max()
+            .unwrap_or(0);
+
+        ((fee_bps as u64)
+            .checked_mul(BPS_DENOMINATOR.checked_sub(discount_bps as u64).unwrap())
+            .unwrap()
+            .checked_div(BPS_DENOMINATOR)
+            .unwrap()) as u16
+    }
+}
 
-#[derive(Accounts)]
-pub struct AlterLiquidity<'info> {
-    user: Signer<'info>,
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct VolumeTier {
+    // Rolling volume a trader needs in a pool to qualify, in raw units of the torrent's
+    // volume quote mint
+    pub min_volume: u64,
 
-    #[account(
-        mut, has_one = liquidity_token_mint,
-        constraint = torrent.pools[pool.index as usize] == pool.key() @ CustomError::TorrentPoolMismatch
-    )]
-    torrent: Box<Account<'info, Torrent>>,
-    #[account(mut, has_one = torrent)]
-    pool: Box<Account<'info, Pool>>,
+    // Share of the swap fee waived, in basis points of it. Zero marks a free slot
+    pub discount_bps: u16,
+}
+
+impl VolumeTier {
+    pub const SIZE: usize = 8 + 2;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Admin,
+    PoolCreator,
+    FeeManager,
+    Pauser,
+    Gatekeeper,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct Roles {
+    // Assigns roles, delists pools and changes torrent-wide parameters
+    pub admin: Pubkey,
+
+    // Creates pools while creation isn't permissionless, and is never charged for it
+    pub pool_creator: Pubkey,
+
+    // Manages fee tiers and pool fees
+    pub fee_manager: Pubkey,
+
+    // Pauses and unpauses swaps and deposits
+    pub pauser: Pubkey,
+
+    // Issues and revokes allowances for allow-listed pools
+    pub gatekeeper: Pubkey,
+}
+
+impl Roles {
+    pub const SIZE: usize = 32 + 32 + 32 + 32 + 32;
+
+    pub fn new(key: Pubkey) -> Self {
+        Roles {
+            admin: key,
+            pool_creator: key,
+            fee_manager: key,
+            pauser: key,
+            gatekeeper: key,
+        }
+    }
+
+    /// Key holding `role`, or the default pubkey if it's been revoked
+    pub fn get(&self, role: Role) -> Pubkey {
+        match role {
+            Role::Admin => self.admin,
+            Role::PoolCreator => self.pool_creator,
+            Role::FeeManager => self.fee_manager,
+            Role::Pauser => self.pauser,
+            Role::Gatekeeper => self.gatekeeper,
+        }
+    }
+
+    pub fn set(&mut self, role: Role, key: Pubkey) {
+        match role {
+            Role::Admin => self.admin = key,
+            Role::PoolCreator => self.pool_creator = key,
+            Role::FeeManager => self.fee_manager = key,
+            Role::Pauser => self.pauser = key,
+            Role::Gatekeeper => self.gatekeeper = key,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct Multisig {
+    // Member keys. Default pubkeys mark free slots
+    pub signers: [Pubkey; MAX_MULTISIG_SIGNERS],
+
+    // Members that must sign; zero disables the multisig
+    pub threshold: u8,
+}
+
+impl Multisig {
+    pub const SIZE: usize = (32 * MAX_MULTISIG_SIGNERS) + 1;
+
+    pub fn new(signers: &[Pubkey], threshold: u8) -> Result<Self> {
+        require!(
+            signers.len() <= MAX_MULTISIG_SIGNERS && threshold as usize <= signers.len(),
+            CustomError::InvalidMultisig
+        );
+        for (i, signer) in signers.iter().enumerate() {
+            require!(
+                *signer != Pubkey::default() && !signers[..i].contains(signer),
+                CustomError::InvalidMultisig
+            );
+        }
+
+        let mut multisig = Multisig {
+            threshold,
+            ..Multisig::default()
+        };
+        multisig.signers[..signers.len()].copy_from_slice(signers);
+
+        Ok(multisig)
+    }
+
+    /// Fails unless at least `threshold` distinct members signed among `accounts`,
+    /// which are the instruction's remaining accounts.
+    pub fn check(&self, accounts: &[AccountInfo]) -> Result<()> {
+        if self.threshold == 0 {
+            return Ok(());
+        }
+
+        let approvals = self
+            .signers
+            .iter()
+            .filter(|member| **member != Pubkey::default())
+            .filter(|member| {
+                accounts
+                    .iter()
+                    .any(|account| account.is_signer && account.key == *member)
+            })
+            .count();
+        require!(
+            approvals >= self.threshold as usize,
+            CustomError::MultisigThresholdNotMet
+        );
+
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct PoolCreationConfig {
+    // Lets anyone create pools, not just the pool creator role
+    pub permissionless: bool,
+
+    // Charged to creators other than the pool creator role
+    pub fee: u64,
+
+    // Mint the fee is paid in, or the default pubkey for lamports
+    pub fee_mint: Pubkey,
+
+    // Receives creation fees
+    pub treasury: Pubkey,
+}
+
+impl PoolCreationConfig {
+    pub const SIZE: usize = 1 + 8 + 32 + 32;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct MintPolicy {
+    // Freeze authorities are common on stablecoins, so they're only rejected on request
+    pub reject_freeze_authority: bool,
+    pub allow_permanent_delegate: bool,
+    pub allow_non_transferable: bool,
+}
+
+impl MintPolicy {
+    pub const SIZE: usize = 1 + 1 + 1;
+
+    pub fn check(&self, mint: &InterfaceAccount<token_interface::Mint>) -> Result<()> {
+        require!(
+            !self.reject_freeze_authority || mint.freeze_authority.is_none(),
+            CustomError::MintHasFreezeAuthority
+        );
+
+        let mint_info = mint.to_account_info();
+        if *mint_info.owner != spl_token_2022::ID {
+            return Ok(());
+        }
+
+        let mint_data = mint_info.try_borrow_data()?;
+        let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+        // Extension types newer than the spl-token-2022 anchor-spl builds with, transfer
+        // hooks among them, fail to parse and can't be vetted.
+        let extension_types = mint_state
+            .get_extension_types()
+            .map_err(|_| error!(CustomError::MintHasUnsupportedExtension))?;
+        for extension_type in extension_types {
+            match extension_type {
+                ExtensionType::PermanentDelegate => require!(
+                    self.allow_permanent_delegate,
+                    CustomError::MintHasPermanentDelegate
+                ),
+                ExtensionType::NonTransferable => require!(
+                    self.allow_non_transferable,
+                    CustomError::MintNonTransferable
+                ),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[account]
+pub struct Pool {
+    // Pool's position in the torrent
+    pub index: u8,
+
+    // The torrent this pool belongs to
+    pub torrent: Pubkey,
+
+    // liquidity tokens minted by this pool
+    pub pool_liquidity: u64,
+
+    // Fee tier the pool was created under, part of its address
+    pub fee_tier: u16,
+
+    // Fee charged on swaps, in basis points of the input amount
+    pub fee_bps: u16,
+
+    // Account that created this pool
+    pub creator: Pubkey,
+
+    // Delisted pools only allow withdrawals
+    pub delisted: bool,
+
+    // Volatility-based fee settings, used in place of `fee_bps` when enabled
+    pub dynamic_fee: DynamicFeeConfig,
+
+    // Recent price movement, in basis points, that drives the dynamic fee
+    pub volatility_accumulator: u64,
+
+    // Slot of the last swap that updated the volatility accumulator
+    pub last_volatility_slot: u64,
+
+    // Deposit caps on each vault's balance and on pool liquidity. Zero means uncapped
+    pub max_reserve_x: u64,
+    pub max_reserve_y: u64,
+    pub max_pool_liquidity: u64,
+
+    // Only users holding an allowance may deposit or swap
+    pub allow_list: bool,
+}
+
+impl Pool {
+    pub const SIZE: usize =
+        1 + 32 + 8 + 2 + 2 + 32 + 1 + DynamicFeeConfig::SIZE + 8 + 8 + 8 + 8 + 8 + 1;
+
+    /// Fails if a deposit would leave the pool's reserves or liquidity above its caps.
+    pub fn check_deposit_caps(&self, reserve_x: u64, reserve_y: u64, liquidity: u64) -> Result<()> {
+        let within = |amount: u64, cap: u64| cap == 0 || amount <= cap;
+        require!(
+            within(reserve_x, self.max_reserve_x)
+                && within(reserve_y, self.max_reserve_y)
+                && within(liquidity, self.max_pool_liquidity),
+            CustomError::DepositCapExceeded
+        );
+
+        Ok(())
+    }
+
+    /// Swap fee in effect at `slot`. With dynamic fees enabled this grows with the
+    /// volatility left over from recent swaps.
+    pub fn current_fee_bps(&self, slot: u64) -> u16 {
+        let dynamic_fee = &self.dynamic_fee;
+        if !dynamic_fee.enabled {
+            return self.fee_bps;
+        }
+
+        let variable_fee = (self.volatility_at(slot) as u128)
+            .checked_mul(dynamic_fee.variable_fee_control as u128)
+            .unwrap()
+            .checked_div(BPS_DENOMINATOR as u128)
+            .unwrap();
+
+        (dynamic_fee.min_fee_bps as u128)
+            .checked_add(variable_fee)
+            .unwrap()
+            .min(dynamic_fee.max_fee_bps as u128) as u16
+    }
+
+    /// Accumulated volatility, in basis points, carried into a swap at `slot`. It's kept
+    /// whole within the filter period, reduced until the decay period, then dropped.
+    fn volatility_at(&self, slot: u64) -> u64 {
+        let dynamic_fee = &self.dynamic_fee;
+        let elapsed = slot.saturating_sub(self.last_volatility_slot);
+
+        if elapsed < dynamic_fee.filter_period {
+            self.volatility_accumulator
+        } else if elapsed < dynamic_fee.decay_period {
+            ((self.volatility_accumulator as u128)
+                .checked_mul(dynamic_fee.reduction_factor as u128)
+                .unwrap()
+                .checked_div(BPS_DENOMINATOR as u128)
+                .unwrap()) as u64
+        } else {
+            0
+        }
+    }
+
+    /// Adds the relative price move caused by a swap to the volatility accumulator.
+    pub fn record_price_move(
+        &mut self,
+        reserve_in_before: u64,
+        reserve_out_before: u64,
+        reserve_in_after: u64,
+        reserve_out_after: u64,
+        slot: u64,
+    ) {
+        if !self.dynamic_fee.enabled {
+            return;
+        }
+
+        // Price of the input token is reserve_out / reserve_in, and it only falls on a swap
+        let price_after = (reserve_out_after as u128)
+            .checked_mul(reserve_in_before as u128)
+            .unwrap();
+        let price_before = (reserve_out_before as u128)
+            .checked_mul(reserve_in_after as u128)
+            .unwrap();
+        // An emptied reserve has no price to move from
+        if price_before == 0 {
+            return;
+        }
+        let price_move_bps = price_before
+            .checked_sub(price_after)
+            .unwrap()
+            .checked_mul(BPS_DENOMINATOR as u128)
+            .map_or(u64::MAX, |change| {
+                change
+                    .checked_div(price_before)
+                    .unwrap()
+                    .min(u64::MAX as u128) as u64
+            });
+
+        self.volatility_accumulator = self
+            .volatility_at(slot)
+            .saturating_add(price_move_bps)
+            .min(MAX_VOLATILITY_ACCUMULATOR);
+        self.last_volatility_slot = slot;
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct DynamicFeeConfig {
+    pub enabled: bool,
+
+    // Bounds on the swap fee, in basis points
+    pub min_fee_bps: u16,
+    pub max_fee_bps: u16,
+
+    // Fee added per basis point of accumulated volatility, in basis points of a basis point
+    pub variable_fee_control: u32,
+
+    // Slots after a swap during which accumulated volatility is kept whole
+    pub filter_period: u64,
+
+    // Slots after a swap after which accumulated volatility is dropped
+    pub decay_period: u64,
+
+    // Share of accumulated volatility kept between the two periods, in basis points
+    pub reduction_factor: u16,
+}
+
+impl DynamicFeeConfig {
+    pub const SIZE: usize = 1 + 2 + 2 + 4 + 8 + 8 + 2;
+
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.min_fee_bps <= self.max_fee_bps && (self.max_fee_bps as u64) < BPS_DENOMINATOR,
+            CustomError::InvalidFee
+        );
+        require!(
+            self.filter_period <= self.decay_period
+                && (self.reduction_factor as u64) <= BPS_DENOMINATOR,
+            CustomError::InvalidDynamicFeeConfig
+        );
+
+        Ok(())
+    }
+}
+
+/// Result of `quote_swap`, before any volume discount or referral fee
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct SwapQuote {
+    pub amount_out: u64,
+    pub fee: u64,
+    pub fee_bps: u16,
+}
+
+/// Result of `quote_add_liquidity`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct AddLiquidityQuote {
+    // Y tokens deposited alongside the X amount
+    pub amount_y: u64,
+    pub liquidity: u64,
+}
+
+/// Result of `quote_remove_liquidity`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct RemoveLiquidityQuote {
+    pub amount_x: u64,
+    pub amount_y: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum SwapDirection {
+    XToY,
+    YToX,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum PoolSide {
+    X,
+    Y,
+}
+
+#[account]
+pub struct PendingChange {
+    // The torrent this change applies to
+    pub torrent: Pubkey,
+
+    // Index of this change among the torrent's queued changes
+    pub id: u64,
+
+    // Key that queued the change, refunded its rent when it's closed
+    pub proposer: Pubkey,
+
+    pub change: ParameterChange,
+
+    // Unix timestamp from which the change can be executed
+    pub eta: i64,
+
+    pub bump: u8,
+}
+
+impl PendingChange {
+    pub const SIZE: usize = 32 + 8 + 32 + ParameterChange::SIZE + 8 + 1;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub enum ParameterChange {
+    PoolFee {
+        pool: Pubkey,
+        fee_bps: u16,
+    },
+    DynamicFee {
+        pool: Pubkey,
+        dynamic_fee: DynamicFeeConfig,
+    },
+    MintPolicy {
+        mint_policy: MintPolicy,
+    },
+    PoolCreation {
+        pool_creation: PoolCreationConfig,
+    },
+    TimelockDelay {
+        delay: i64,
+    },
+    MaxReferralShare {
+        max_share_bps: u16,
+    },
+    VolumeTiers {
+        quote_mint: Pubkey,
+        volume_tiers: [VolumeTier; MAX_VOLUME_TIERS],
+    },
+}
+
+impl ParameterChange {
+    // Discriminator plus the largest variant
+    pub const SIZE: usize = 1 + PoolCreationConfig::SIZE;
+
+    /// Role allowed to queue and cancel this change, besides the admin
+    pub fn role(&self) -> Role {
+        match self {
+            ParameterChange::PoolFee { .. }
+            | ParameterChange::DynamicFee { .. }
+            | ParameterChange::MaxReferralShare { .. }
+            | ParameterChange::VolumeTiers { .. } => Role::FeeManager,
+            _ => Role::Admin,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            ParameterChange::PoolFee { fee_bps, .. } => {
+                require!((*fee_bps as u64) < BPS_DENOMINATOR, CustomError::InvalidFee)
+            }
+            ParameterChange::DynamicFee { dynamic_fee, .. } => dynamic_fee.validate()?,
+            ParameterChange::TimelockDelay { delay } => {
+                require!(*delay >= 0, CustomError::InvalidTimelockDelay)
+            }
+            ParameterChange::MaxReferralShare { max_share_bps } => require!(
+                (*max_share_bps as u64) <= BPS_DENOMINATOR,
+                CustomError::InvalidFee
+            ),
+            ParameterChange::VolumeTiers { volume_tiers, .. } => require!(
+                volume_tiers
+                    .iter()
+                    .all(|tier| (tier.discount_bps as u64) <= BPS_DENOMINATOR),
+                CustomError::InvalidFee
+            ),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Applies the change to `torrent`, or for pool-level changes to `pool`, which must
+    /// be the pool targeted.
+    pub fn apply(
+        &self,
+        torrent: &mut Torrent,
+        pool: Option<(Pubkey, &mut Pool)>,
+        slot: u64,
+    ) -> Result<()> {
+        let target_pool = |target: Pubkey| match pool {
+            Some((key, pool)) if key == target => Ok(pool),
+            _ => err!(CustomError::ChangeTargetMismatch),
+        };
+
+        match *self {
+            ParameterChange::PoolFee { pool, fee_bps } => target_pool(pool)?.fee_bps = fee_bps,
+            ParameterChange::DynamicFee { pool, dynamic_fee } => {
+                let pool = target_pool(pool)?;
+                pool.dynamic_fee = dynamic_fee;
+                pool.volatility_accumulator = 0;
+                pool.last_volatility_slot = slot;
+            }
+            ParameterChange::MintPolicy { mint_policy } => torrent.mint_policy = mint_policy,
+            ParameterChange::PoolCreation { pool_creation } => {
+                torrent.pool_creation = pool_creation
+            }
+            ParameterChange::TimelockDelay { delay } => torrent.timelock_delay = delay,
+            ParameterChange::MaxReferralShare { max_share_bps } => {
+                torrent.max_referral_share_bps = max_share_bps
+            }
+            ParameterChange::VolumeTiers {
+                quote_mint,
+                volume_tiers,
+            } => {
+                torrent.volume_quote_mint = quote_mint;
+                torrent.volume_tiers = volume_tiers;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[event]
+pub struct ChangeQueued {
+    pub torrent: Pubkey,
+    pub id: u64,
+    pub change: ParameterChange,
+    pub eta: i64,
+}
+
+#[event]
+pub struct ChangeExecuted {
+    pub torrent: Pubkey,
+    pub id: u64,
+}
+
+#[event]
+pub struct ChangeCancelled {
+    pub torrent: Pubkey,
+    pub id: u64,
+}
+
+#[account]
+pub struct Allowance {
+    // The torrent whose gatekeeper issued this allowance
+    pub torrent: Pubkey,
+
+    // User allowed into the torrent's allow-listed pools
+    pub user: Pubkey,
+
+    pub bump: u8,
+}
+
+impl Allowance {
+    pub const SIZE: usize = 32 + 32 + 1;
+}
+
+#[account]
+pub struct Referrer {
+    // The torrent this referrer routes swaps to
+    pub torrent: Pubkey,
+
+    // Owner of the token accounts referral fees are paid to
+    pub owner: Pubkey,
+
+    // Share of each referred swap's fee, in basis points, capped by the torrent's limit
+    pub share_bps: u16,
+
+    // Referred swaps and their summed input amounts, in raw units of each input token
+    pub swap_count: u64,
+    pub volume: u128,
+
+    // Referral fees paid out, in raw units of each input token
+    pub fees_earned: u128,
+
+    pub bump: u8,
+}
+
+impl Referrer {
+    pub const SIZE: usize = 32 + 32 + 2 + 8 + 16 + 16 + 1;
+
+    /// Fails unless `trader`'s swap paying in `mint_in` can credit this referrer through
+    /// `wallet`. Traders can't refer themselves, or the referral share would be a fee
+    /// discount for anyone who registers a referrer.
+    pub fn check_swap(
+        &self,
+        trader: &Pubkey,
+        wallet: &token_interface::TokenAccount,
+        mint_in: &Pubkey,
+    ) -> Result<()> {
+        require!(
+            wallet.owner == self.owner && wallet.mint == *mint_in,
+            CustomError::ReferrerMismatch
+        );
+        require!(self.owner != *trader, CustomError::SelfReferral);
+
+        Ok(())
+    }
+}
+
+#[account]
+pub struct TraderStats {
+    // The pool whose swaps are counted
+    pub pool: Pubkey,
+
+    pub trader: Pubkey,
+
+    // Volume per day, in raw units of the torrent's volume quote mint, indexed by unix
+    // day modulo the window
+    pub daily_volume: [u64; VOLUME_WINDOW_DAYS],
+
+    // Unix day of the latest recorded swap
+    pub last_day: i64,
+
+    pub bump: u8,
+}
+
+impl TraderStats {
+    pub const SIZE: usize = 32 + 32 + (8 * VOLUME_WINDOW_DAYS) + 8 + 1;
+
+    /// Volume over the window of days ending on the day of `now`.
+    pub fn rolling_volume(&self, now: i64) -> u128 {
+        let today = now.div_euclid(SECONDS_PER_DAY);
+        let window_start = today - VOLUME_WINDOW_DAYS as i64 + 1;
+
+        (window_start.max(self.last_day - VOLUME_WINDOW_DAYS as i64 + 1)..=self.last_day)
+            .map(|day| self.daily_volume[Self::bucket(day)] as u128)
+            .sum()
+    }
+
+    pub fn record_volume(&mut self, amount: u64, now: i64) {
+        let today = now.div_euclid(SECONDS_PER_DAY);
+
+        // Clear the buckets of days without swaps since the last one
+        if today > self.last_day {
+            let first_stale = (self.last_day + 1).max(today - VOLUME_WINDOW_DAYS as i64 + 1);
+            for day in first_stale..=today {
+                self.daily_volume[Self::bucket(day)] = 0;
+            }
+            self.last_day = today;
+        }
+
+        let bucket = &mut self.daily_volume[Self::bucket(today)];
+        *bucket = bucket.saturating_add(amount);
+    }
+
+    fn bucket(day: i64) -> usize {
+        day.rem_euclid(VOLUME_WINDOW_DAYS as i64) as usize
+    }
+}
+
+#[account]
+pub struct Farm {
+    // The torrent whose liquidity tokens are staked here
+    pub torrent: Pubkey,
+
+    // Index of this farm among the torrent's farms
+    pub id: u64,
+
+    // Reward schedules paying out to stakers. Only the first `stream_count` are in use
+    pub reward_streams: [RewardStream; MAX_REWARD_STREAMS],
+    pub stream_count: u8,
+
+    // Liquidity tokens staked in the farm
+    pub total_staked: u64,
+
+    pub bump: u8,
+}
+
+impl Farm {
+    pub const SIZE: usize = 32 + 8 + (RewardStream::SIZE * MAX_REWARD_STREAMS) + 1 + 8 + 1;
+
+    pub fn streams(&self) -> &[RewardStream] {
+        &self.reward_streams[..self.stream_count as usize]
+    }
+
+    /// Accrues every stream's emissions up to `now`.
+    pub fn update(&mut self, now: i64) {
+        let total_staked = self.total_staked;
+        for stream in self.reward_streams[..self.stream_count as usize].iter_mut() {
+            stream.update(now, total_staked);
+        }
+    }
+
+    pub fn stream_index(&self, reward_mint: &Pubkey) -> Result<usize> {
+        self.streams()
+            .iter()
+            .position(|stream| stream.reward_mint == *reward_mint)
+            .ok_or_else(|| error!(CustomError::RewardStreamMismatch))
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct RewardStream {
+    pub reward_mint: Pubkey,
+    pub reward_vault: Pubkey,
+
+    // Account that paid for the stream's schedule
+    pub funder: Pubkey,
+
+    // Reward tokens emitted per second, shared between stakers
+    pub emission_rate: u64,
 
-    #[account(mut, seeds = [b"x_vault".as_ref(), pool.key().as_ref()], bump)]
-    x_token_vault: Box<Account<'info, TokenAccount>>,
-    #[account(mut, seeds = [b"y_vault".as_ref(), pool.key().as_ref()], bump)]
-    y_token_vault: Box<Account<'info, TokenAccount>>,
+    // Unix timestamps rewards are emitted between. The end moves later while nothing is staked
+    pub start_timestamp: i64,
+    pub end_timestamp: i64,
 
-    #[account(mut)]
-    liquidity_token_mint: Box<Account<'info, Mint>>,
+    // Time up to which rewards have been accrued into `reward_per_share`
+    pub last_update_timestamp: i64,
 
-    /// User's token accounts
-    #[account(
-        mut,
-        constraint = user_x_wallet.owner == user.key(),
-        constraint = user_x_wallet.mint == x_token_vault.mint,
-    )]
-    user_x_wallet: Box<Account<'info, TokenAccount>>,
-    #[account(
-        mut,
-        constraint = user_y_wallet.owner == user.key(),
-        constraint = user_y_wallet.mint == y_token_vault.mint,
-    )]
-    user_y_wallet: Box<Account<'info, TokenAccount>>,
-    #[account(
-        mut,
-        constraint = user_liquidity_token_wallet.owner == user.key(),
-        constraint = user_liquidity_token_wallet.mint == liquidity_token_mint.key(),
-    )]
-    user_liquidity_token_wallet: Box<Account<'info, TokenAccount>>,
+    // Rewards accrued per staked liquidity token, scaled by `REWARD_PRECISION`
+    pub reward_per_share: u128,
+}
 
-    token_program: Program<'info, Token>,
+impl RewardStream {
+    pub const SIZE: usize = 32 + 32 + 32 + 8 + 8 + 8 + 8 + 16;
+
+    /// Accrues emissions up to `now` into `reward_per_share`. While nothing is staked the
+    /// stream is paused: the rest of its schedule is pushed back by the idle time, so
+    /// everything funded is eventually paid out to stakers.
+    pub fn update(&mut self, now: i64, total_staked: u64) {
+        if now <= self.last_update_timestamp {
+            return;
+        }
+
+        if total_staked == 0 {
+            let remaining = self
+                .end_timestamp
+                .checked_sub(self.last_update_timestamp)
+                .unwrap();
+            self.end_timestamp = now.checked_add(remaining).unwrap();
+            self.last_update_timestamp = now;
+            return;
+        }
+
+        let now = now.min(self.end_timestamp);
+        if now <= self.last_update_timestamp {
+            return;
+        }
+
+        let emitted = (now.checked_sub(self.last_update_timestamp).unwrap() as u128)
+            .checked_mul(self.emission_rate as u128)
+            .unwrap();
+        self.reward_per_share = self
+            .reward_per_share
+            .checked_add(
+                emitted
+                    .checked_mul(REWARD_PRECISION)
+                    .unwrap()
+                    .checked_div(total_staked as u128)
+                    .unwrap(),
+            )
+            .unwrap();
+        self.last_update_timestamp = now;
+    }
 }
 
 #[account]
-pub struct Torrent {
-    // Authority
-    pub authority: Pubkey,
+pub struct StakeAccount {
+    pub farm: Pubkey,
 
-    // Liquidity token mint
-    pub liquidity_token_mint: Pubkey,
+    // User that staked, the only one able to unstake or claim
+    pub owner: Pubkey,
 
-    // Total supply of liquidity tokens
-    pub torrent_liquidity: u64,
+    // Liquidity tokens staked
+    pub amount: u64,
 
-    // Associated Pools
-    pub pools: [Pubkey; MAX_POOLS],
+    // Reward accounting for each of the farm's streams, by stream index
+    pub rewards: [StakeRewards; MAX_REWARD_STREAMS],
 
     pub bump: u8,
 }
 
-impl Torrent {
-    const SIZE: usize = 32 + 32 + 8 + (32 * 10) + 1;
-
-    pub fn register_pool(&mut self, new_pool: Pubkey) -> Result<u8> {
-        for (index, pool) in self.pools.iter_mut().enumerate() {
-            if *pool != Pubkey::default() {
-                continue;
-            }
+impl StakeAccount {
+    pub const SIZE: usize = 32 + 32 + 8 + (StakeRewards::SIZE * MAX_REWARD_STREAMS) + 1;
 
-            *pool = new_pool;
+    /// Moves rewards earned since the last settlement into each stream's pending rewards.
+    pub fn settle(&mut self, farm: &Farm) {
+        for (rewards, stream) in self.rewards.iter_mut().zip(farm.streams()) {
+            let accrued = (self.amount as u128)
+                .checked_mul(stream.reward_per_share)
+                .unwrap();
+            let earned = accrued
+                .checked_sub(rewards.reward_debt)
+                .unwrap()
+                .checked_div(REWARD_PRECISION)
+                .unwrap() as u64;
+            rewards.pending_rewards = rewards.pending_rewards.checked_add(earned).unwrap();
+            rewards.reward_debt = rewards
+                .reward_debt
+                .checked_add(earned as u128 * REWARD_PRECISION)
+                .unwrap();
+        }
+    }
 
-            return Ok(index as u8);
+    /// Marks everything accrued so far as accounted for, after `amount` changes.
+    pub fn reset_reward_debt(&mut self, farm: &Farm) {
+        for (rewards, stream) in self.rewards.iter_mut().zip(farm.streams()) {
+            rewards.reward_debt = (self.amount as u128)
+                .checked_mul(stream.reward_per_share)
+                .unwrap();
         }
-        Err(error!(CustomError::MaxPoolLimit))
     }
+
+    /// Empties a stream's pending rewards, returning the amount to pay out.
+    pub fn take_rewards(&mut self, stream_index: usize) -> u64 {
+        std::mem::take(&mut self.rewards[stream_index].pending_rewards)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct StakeRewards {
+    // Share of the stream's `reward_per_share` already accounted for, scaled by `REWARD_PRECISION`
+    pub reward_debt: u128,
+
+    // Rewards settled but not yet claimed
+    pub pending_rewards: u64,
+}
+
+impl StakeRewards {
+    pub const SIZE: usize = 16 + 8;
 }
 
 #[account]
-pub struct Pool {
-    // Pool's position in the torrent
-    pub index: u8,
+pub struct Position {
+    // The pool this position provides liquidity to
+    pub pool: Pubkey,
 
-    // The torrent this pool belongs to
-    pub torrent: Pubkey,
+    // Mint of the token representing ownership of this position
+    pub position_mint: Pubkey,
 
-    // liquidity tokens minted by this pool
-    pub pool_liquidity: u64,
+    // Pool liquidity owned by this position
+    pub liquidity: u64,
+
+    // Liquidity can't be removed before this unix timestamp
+    pub unlock_timestamp: i64,
+
+    pub bump: u8,
 }
 
-impl Pool {
-    pub const SIZE: usize = 1 + 32 + 8;
+impl Position {
+    pub const SIZE: usize = 32 + 32 + 8 + 8 + 1;
 }
 
 #[error_code]
@@ -461,4 +3951,869 @@ pub enum CustomError {
     TorrentPoolMismatch,
     #[msg("Burn exceeds pool limits")]
     ExcessiveBurn,
+    #[msg("Position token not held by signer")]
+    PositionNotHeld,
+    #[msg("Position is still locked")]
+    PositionLocked,
+    #[msg("Mint has a freeze authority")]
+    MintHasFreezeAuthority,
+    #[msg("Mint has a permanent delegate")]
+    MintHasPermanentDelegate,
+    #[msg("Mint has a transfer hook or another extension the program can't vet")]
+    MintHasUnsupportedExtension,
+    #[msg("Mint is non-transferable")]
+    MintNonTransferable,
+    #[msg("Swap output below minimum")]
+    SlippageExceeded,
+    #[msg("User wallet is missing and the side isn't native SOL paid through the native wallet")]
+    MissingUserWallet,
+    #[msg("Native wallet is already in use for the other side of the pool")]
+    NativeWalletInUse,
+    #[msg("Transaction deadline has passed")]
+    Expired,
+    #[msg("Signer is not allowed to perform this action")]
+    Unauthorized,
+    #[msg("Token accounts for the pool creation fee are missing")]
+    MissingCreationFeeAccounts,
+    #[msg("Pool has been delisted")]
+    PoolDelisted,
+    #[msg("Fee tier is not registered on the torrent")]
+    UnknownFeeTier,
+    #[msg("Fee tier is already registered")]
+    DuplicateFeeTier,
+    #[msg("No free fee tier slot in torrent")]
+    MaxFeeTierLimit,
+    #[msg("Fee is out of range")]
+    InvalidFee,
+    #[msg("Dynamic fee periods or reduction factor are invalid")]
+    InvalidDynamicFeeConfig,
+    #[msg("Torrent is paused")]
+    Paused,
+    #[msg("Timelock on this change has not elapsed")]
+    TimelockNotElapsed,
+    #[msg("Timelock delay can't be negative")]
+    InvalidTimelockDelay,
+    #[msg("Pool passed does not match the change being executed")]
+    ChangeTargetMismatch,
+    #[msg("The admin role can't be revoked")]
+    InvalidRoleAssignment,
+    #[msg("Multisig signers must be unique and at least the threshold in number")]
+    InvalidMultisig,
+    #[msg("Not enough multisig members signed")]
+    MultisigThresholdNotMet,
+    #[msg("Deposit would exceed the pool's caps")]
+    DepositCapExceeded,
+    #[msg("Pool is allow-listed and the user has no allowance")]
+    NotAllowListed,
+    #[msg("Farm needs a non-zero emission rate and a future start before its end")]
+    InvalidFarmSchedule,
+    #[msg("No free reward stream in farm")]
+    MaxRewardStreams,
+    #[msg("Accounts passed don't match the farm's reward streams")]
+    RewardStreamMismatch,
+    #[msg("Referral share is above the torrent's limit")]
+    InvalidReferralShare,
+    #[msg("Referrer and referrer wallet must be passed together and match the input token")]
+    ReferrerMismatch,
+    #[msg("Traders can't be credited as their own referrer")]
+    SelfReferral,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test accounts are leaked so they can be borrowed for 'info
+    fn account(key: Pubkey, owner: Pubkey, data: Vec<u8>, is_signer: bool) -> AccountInfo<'static> {
+        AccountInfo::new(
+            Box::leak(Box::new(key)),
+            is_signer,
+            true,
+            Box::leak(Box::new(10_000_000)),
+            Box::leak(data.into_boxed_slice()),
+            Box::leak(Box::new(owner)),
+            false,
+            0,
+        )
+    }
+
+    mod farm {
+        use super::*;
+
+        fn farm(streams: &[(u64, i64, i64)]) -> Farm {
+            let mut farm = Farm {
+                torrent: Pubkey::new_unique(),
+                id: 0,
+                reward_streams: [RewardStream::default(); MAX_REWARD_STREAMS],
+                stream_count: streams.len() as u8,
+                total_staked: 0,
+                bump: 0,
+            };
+            for (stream, (emission_rate, start, end)) in farm.reward_streams.iter_mut().zip(streams)
+            {
+                stream.emission_rate = *emission_rate;
+                stream.start_timestamp = *start;
+                stream.end_timestamp = *end;
+                stream.last_update_timestamp = *start;
+            }
+            farm
+        }
+
+        fn stake_account() -> StakeAccount {
+            StakeAccount {
+                farm: Pubkey::default(),
+                owner: Pubkey::new_unique(),
+                amount: 0,
+                rewards: [StakeRewards::default(); MAX_REWARD_STREAMS],
+                bump: 0,
+            }
+        }
+
+        // Same steps as `stake_lp` and `unstake_lp`
+        fn stake(farm: &mut Farm, account: &mut StakeAccount, amount: u64, now: i64) {
+            farm.update(now);
+            account.settle(farm);
+            account.amount += amount;
+            account.reset_reward_debt(farm);
+            farm.total_staked += amount;
+        }
+
+        fn unstake(farm: &mut Farm, account: &mut StakeAccount, amount: u64, now: i64) {
+            farm.update(now);
+            account.settle(farm);
+            account.amount -= amount;
+            account.reset_reward_debt(farm);
+            farm.total_staked -= amount;
+        }
+
+        // Same steps as `claim_rewards`
+        fn claim(farm: &mut Farm, account: &mut StakeAccount, stream: usize, now: i64) -> u64 {
+            farm.update(now);
+            account.settle(farm);
+            account.take_rewards(stream)
+        }
+
+        #[test]
+        fn streams_accrue_independently() {
+            let mut farm = farm(&[(10, 0, 100), (3, 50, 150)]);
+            let mut alice = stake_account();
+            stake(&mut farm, &mut alice, 1_000, 0);
+
+            assert_eq!(claim(&mut farm, &mut alice, 0, 80), 800);
+            assert_eq!(claim(&mut farm, &mut alice, 1, 80), 90);
+
+            // Each stream stops at its own end
+            assert_eq!(claim(&mut farm, &mut alice, 0, 200), 200);
+            assert_eq!(claim(&mut farm, &mut alice, 1, 200), 210);
+        }
+
+        #[test]
+        fn stakers_share_emissions_by_stake_and_time() {
+            let mut farm = farm(&[(100, 0, 100)]);
+            let mut alice = stake_account();
+            let mut bob = stake_account();
+
+            stake(&mut farm, &mut alice, 250, 0);
+            // Bob joins halfway with the same stake, then leaves early
+            stake(&mut farm, &mut bob, 250, 50);
+            unstake(&mut farm, &mut bob, 250, 90);
+
+            assert_eq!(claim(&mut farm, &mut alice, 0, 100), 5_000 + 2_000 + 1_000);
+            assert_eq!(claim(&mut farm, &mut bob, 0, 100), 2_000);
+        }
+
+        #[test]
+        fn rewards_stay_claimable_after_unstaking() {
+            let mut farm = farm(&[(10, 0, 100)]);
+            let mut alice = stake_account();
+            stake(&mut farm, &mut alice, 50, 0);
+            unstake(&mut farm, &mut alice, 50, 40);
+
+            assert_eq!(claim(&mut farm, &mut alice, 0, 70), 400);
+            assert_eq!(claim(&mut farm, &mut alice, 0, 100), 0);
+        }
+
+        #[test]
+        fn streams_pause_while_nothing_is_staked() {
+            let mut farm = farm(&[(10, 0, 100)]);
+            let mut alice = stake_account();
+
+            stake(&mut farm, &mut alice, 50, 30);
+            assert_eq!(farm.reward_streams[0].end_timestamp, 130);
+
+            unstake(&mut farm, &mut alice, 50, 60);
+            stake(&mut farm, &mut alice, 50, 80);
+            assert_eq!(farm.reward_streams[0].end_timestamp, 150);
+
+            // Everything funded is paid out, just later
+            assert_eq!(claim(&mut farm, &mut alice, 0, 1_000), 1_000);
+        }
+    }
+
+    mod mint_policy {
+        use super::*;
+        use anchor_lang::solana_program::program_option::COption;
+        use spl_token_2022::extension::{
+            non_transferable::NonTransferable, permanent_delegate::PermanentDelegate, AccountType,
+            StateWithExtensionsMut,
+        };
+        use spl_token_2022::state::Mint as MintState;
+
+        fn base(freeze_authority: bool) -> MintState {
+            MintState {
+                decimals: 6,
+                is_initialized: true,
+                freeze_authority: match freeze_authority {
+                    true => COption::Some(Pubkey::new_unique()),
+                    false => COption::None,
+                },
+                ..Default::default()
+            }
+        }
+
+        fn mint(owner: Pubkey, data: Vec<u8>) -> InterfaceAccount<'static, token_interface::Mint> {
+            InterfaceAccount::try_from(&account(Pubkey::new_unique(), owner, data, false)).unwrap()
+        }
+
+        fn spl_token_mint(
+            freeze_authority: bool,
+        ) -> InterfaceAccount<'static, token_interface::Mint> {
+            let mut data = vec![0; MintState::LEN];
+            base(freeze_authority).pack_into_slice(&mut data);
+            mint(anchor_spl::token::ID, data)
+        }
+
+        fn token_2022_mint(
+            extension_types: &[ExtensionType],
+        ) -> InterfaceAccount<'static, token_interface::Mint> {
+            let mut data = vec![0; ExtensionType::get_account_len::<MintState>(extension_types)];
+            {
+                let mut state =
+                    StateWithExtensionsMut::<MintState>::unpack_uninitialized(&mut data).unwrap();
+                for extension_type in extension_types {
+                    match extension_type {
+                        ExtensionType::PermanentDelegate => {
+                            state.init_extension::<PermanentDelegate>(true).unwrap();
+                        }
+                        ExtensionType::NonTransferable => {
+                            state.init_extension::<NonTransferable>(true).unwrap();
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+                state.base = base(false);
+                state.pack_base();
+                state.init_account_type().unwrap();
+            }
+            mint(spl_token_2022::ID, data)
+        }
+
+        // Transfer hooks postdate the spl-token-2022 in use, so the entry is written by
+        // hand: type 14 holding an authority and a program id
+        fn transfer_hook_mint() -> InterfaceAccount<'static, token_interface::Mint> {
+            let tlv_start = spl_token_2022::state::Account::LEN + 1;
+            let mut data = vec![0; tlv_start + 4 + 64];
+            base(false).pack_into_slice(&mut data[..MintState::LEN]);
+            data[tlv_start - 1] = AccountType::Mint as u8;
+            data[tlv_start..tlv_start + 2].copy_from_slice(&14u16.to_le_bytes());
+            data[tlv_start + 2..tlv_start + 4].copy_from_slice(&64u16.to_le_bytes());
+            mint(spl_token_2022::ID, data)
+        }
+
+        #[test]
+        fn default_policy_accepts_freezable_mints() {
+            let policy = MintPolicy::default();
+            assert!(policy.check(&spl_token_mint(true)).is_ok());
+            assert!(policy.check(&spl_token_mint(false)).is_ok());
+            assert!(policy.check(&token_2022_mint(&[])).is_ok());
+        }
+
+        #[test]
+        fn freeze_authority_is_rejected_when_opted_in() {
+            let policy = MintPolicy {
+                reject_freeze_authority: true,
+                ..MintPolicy::default()
+            };
+            assert_eq!(
+                policy.check(&spl_token_mint(true)).err().unwrap(),
+                CustomError::MintHasFreezeAuthority.into()
+            );
+            assert!(policy.check(&spl_token_mint(false)).is_ok());
+        }
+
+        #[test]
+        fn dangerous_extensions_are_rejected_unless_allowed() {
+            let permanent_delegate = token_2022_mint(&[ExtensionType::PermanentDelegate]);
+            let non_transferable = token_2022_mint(&[ExtensionType::NonTransferable]);
+
+            let policy = MintPolicy::default();
+            assert_eq!(
+                policy.check(&permanent_delegate).err().unwrap(),
+                CustomError::MintHasPermanentDelegate.into()
+            );
+            assert_eq!(
+                policy.check(&non_transferable).err().unwrap(),
+                CustomError::MintNonTransferable.into()
+            );
+
+            let policy = MintPolicy {
+                allow_permanent_delegate: true,
+                allow_non_transferable: true,
+                ..MintPolicy::default()
+            };
+            assert!(policy.check(&permanent_delegate).is_ok());
+            assert!(policy.check(&non_transferable).is_ok());
+        }
+
+        #[test]
+        fn extensions_that_cant_be_vetted_are_rejected() {
+            let policy = MintPolicy {
+                allow_permanent_delegate: true,
+                allow_non_transferable: true,
+                ..MintPolicy::default()
+            };
+            assert_eq!(
+                policy.check(&transfer_hook_mint()).err().unwrap(),
+                CustomError::MintHasUnsupportedExtension.into()
+            );
+        }
+    }
+
+    mod multisig {
+        use super::*;
+
+        fn signer(key: Pubkey) -> AccountInfo<'static> {
+            account(key, System::id(), vec![], true)
+        }
+
+        #[test]
+        fn members_must_be_unique_and_meet_the_threshold() {
+            let [a, b] = [Pubkey::new_unique(), Pubkey::new_unique()];
+            assert!(Multisig::new(&[a, b], 2).is_ok());
+            assert!(Multisig::new(&[a, b], 3).is_err());
+            assert!(Multisig::new(&[a, a], 1).is_err());
+            assert!(Multisig::new(&[a, Pubkey::default()], 1).is_err());
+            assert!(Multisig::new(&[a; MAX_MULTISIG_SIGNERS + 1], 1).is_err());
+        }
+
+        #[test]
+        fn disabled_multisig_needs_no_signers() {
+            assert!(Multisig::default().check(&[]).is_ok());
+        }
+
+        #[test]
+        fn threshold_counts_distinct_signing_members() {
+            let members = [
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+            ];
+            let multisig = Multisig::new(&members, 2).unwrap();
+            let not_met = CustomError::MultisigThresholdNotMet.into();
+
+            assert!(multisig
+                .check(&[signer(members[0]), signer(members[2])])
+                .is_ok());
+
+            // The same member twice, a non-member, and a member that didn't sign
+            assert_eq!(
+                multisig
+                    .check(&[signer(members[0]), signer(members[0])])
+                    .err()
+                    .unwrap(),
+                not_met
+            );
+            assert_eq!(
+                multisig
+                    .check(&[signer(members[0]), signer(Pubkey::new_unique())])
+                    .err()
+                    .unwrap(),
+                not_met
+            );
+            assert_eq!(
+                multisig
+                    .check(&[
+                        signer(members[0]),
+                        account(members[1], System::id(), vec![], false)
+                    ])
+                    .err()
+                    .unwrap(),
+                not_met
+            );
+        }
+    }
+
+    mod parameter_change {
+        use super::*;
+
+        fn torrent() -> Torrent {
+            Torrent::try_from_slice(&[0; Torrent::SIZE]).unwrap()
+        }
+
+        fn pool() -> Pool {
+            let mut pool = Pool::try_from_slice(&[0; Pool::SIZE]).unwrap();
+            pool.fee_bps = 30;
+            pool.volatility_accumulator = 500;
+            pool
+        }
+
+        fn dynamic_fee(min_fee_bps: u16, max_fee_bps: u16) -> DynamicFeeConfig {
+            DynamicFeeConfig {
+                enabled: true,
+                min_fee_bps,
+                max_fee_bps,
+                variable_fee_control: 1_000,
+                filter_period: 10,
+                decay_period: 100,
+                reduction_factor: 5_000,
+            }
+        }
+
+        #[test]
+        fn out_of_range_values_are_rejected() {
+            let pool = Pubkey::new_unique();
+            let volume_tier = |discount_bps| VolumeTier {
+                min_volume: 1,
+                discount_bps,
+            };
+
+            assert!(ParameterChange::PoolFee { pool, fee_bps: 30 }
+                .validate()
+                .is_ok());
+            assert!(ParameterChange::PoolFee {
+                pool,
+                fee_bps: 10_000
+            }
+            .validate()
+            .is_err());
+            assert!(ParameterChange::DynamicFee {
+                pool,
+                dynamic_fee: dynamic_fee(50, 10)
+            }
+            .validate()
+            .is_err());
+            assert!(ParameterChange::TimelockDelay { delay: -1 }
+                .validate()
+                .is_err());
+            assert!(ParameterChange::MaxReferralShare {
+                max_share_bps: 10_001
+            }
+            .validate()
+            .is_err());
+            assert!(ParameterChange::VolumeTiers {
+                quote_mint: Pubkey::new_unique(),
+                volume_tiers: [volume_tier(10_001); MAX_VOLUME_TIERS]
+            }
+            .validate()
+            .is_err());
+        }
+
+        #[test]
+        fn fee_changes_belong_to_the_fee_manager() {
+            let pool = Pubkey::new_unique();
+            assert!(ParameterChange::PoolFee { pool, fee_bps: 30 }.role() == Role::FeeManager);
+            assert!(ParameterChange::TimelockDelay { delay: 60 }.role() == Role::Admin);
+        }
+
+        #[test]
+        fn torrent_changes_apply_to_the_torrent() {
+            let mut torrent = torrent();
+
+            ParameterChange::TimelockDelay { delay: 3_600 }
+                .apply(&mut torrent, None, 0)
+                .unwrap();
+            ParameterChange::MaxReferralShare {
+                max_share_bps: 1_500,
+            }
+            .apply(&mut torrent, None, 0)
+            .unwrap();
+
+            assert_eq!(torrent.timelock_delay, 3_600);
+            assert_eq!(torrent.max_referral_share_bps, 1_500);
+        }
+
+        #[test]
+        fn pool_changes_apply_only_to_the_targeted_pool() {
+            let mut torrent = torrent();
+            let (key, mut pool) = (Pubkey::new_unique(), pool());
+
+            ParameterChange::PoolFee {
+                pool: key,
+                fee_bps: 100,
+            }
+            .apply(&mut torrent, Some((key, &mut pool)), 0)
+            .unwrap();
+            assert_eq!(pool.fee_bps, 100);
+
+            let change = ParameterChange::PoolFee {
+                pool: Pubkey::new_unique(),
+                fee_bps: 5,
+            };
+            let mismatch = CustomError::ChangeTargetMismatch.into();
+            assert_eq!(
+                change
+                    .apply(&mut torrent, Some((key, &mut pool)), 0)
+                    .err()
+                    .unwrap(),
+                mismatch
+            );
+            assert_eq!(change.apply(&mut torrent, None, 0).err().unwrap(), mismatch);
+            assert_eq!(pool.fee_bps, 100);
+        }
+
+        #[test]
+        fn dynamic_fee_changes_reset_volatility() {
+            let mut torrent = torrent();
+            let (key, mut pool) = (Pubkey::new_unique(), pool());
+
+            ParameterChange::DynamicFee {
+                pool: key,
+                dynamic_fee: dynamic_fee(10, 50),
+            }
+            .apply(&mut torrent, Some((key, &mut pool)), 1_234)
+            .unwrap();
+
+            assert!(pool.dynamic_fee.enabled);
+            assert_eq!(pool.volatility_accumulator, 0);
+            assert_eq!(pool.last_volatility_slot, 1_234);
+        }
+    }
+
+    mod referrer {
+        use super::*;
+        use anchor_spl::token::spl_token;
+
+        fn referrer(owner: Pubkey) -> Referrer {
+            let mut referrer = Referrer::try_from_slice(&[0; Referrer::SIZE]).unwrap();
+            referrer.owner = owner;
+            referrer.share_bps = 2_000;
+            referrer
+        }
+
+        fn wallet(owner: Pubkey, mint: Pubkey) -> token_interface::TokenAccount {
+            let mut data = vec![0; spl_token::state::Account::LEN];
+            spl_token::state::Account {
+                mint,
+                owner,
+                state: spl_token::state::AccountState::Initialized,
+                ..Default::default()
+            }
+            .pack_into_slice(&mut data);
+            token_interface::TokenAccount::try_deserialize(&mut &data[..]).unwrap()
+        }
+
+        #[test]
+        fn referral_fees_go_to_the_referrers_wallet_for_the_input_token() {
+            let (owner, trader, mint_in) = (
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+            );
+            let referrer = referrer(owner);
+            let mismatch = CustomError::ReferrerMismatch.into();
+
+            assert!(referrer
+                .check_swap(&trader, &wallet(owner, mint_in), &mint_in)
+                .is_ok());
+            assert_eq!(
+                referrer
+                    .check_swap(&trader, &wallet(trader, mint_in), &mint_in)
+                    .err()
+                    .unwrap(),
+                mismatch
+            );
+            assert_eq!(
+                referrer
+                    .check_swap(&trader, &wallet(owner, Pubkey::new_unique()), &mint_in)
+                    .err()
+                    .unwrap(),
+                mismatch
+            );
+        }
+
+        #[test]
+        fn traders_cant_refer_themselves() {
+            let (trader, mint_in) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+            assert_eq!(
+                referrer(trader)
+                    .check_swap(&trader, &wallet(trader, mint_in), &mint_in)
+                    .err()
+                    .unwrap(),
+                CustomError::SelfReferral.into()
+            );
+        }
+    }
+
+    mod trader_stats {
+        use super::*;
+
+        fn trader_stats() -> TraderStats {
+            TraderStats::try_from_slice(&[0; TraderStats::SIZE]).unwrap()
+        }
+
+        fn day(day: i64) -> i64 {
+            day * SECONDS_PER_DAY + 60
+        }
+
+        #[test]
+        fn volume_is_summed_over_the_window() {
+            let mut stats = trader_stats();
+            stats.record_volume(100, day(0));
+            stats.record_volume(200, day(0));
+            stats.record_volume(50, day(5));
+
+            assert_eq!(stats.rolling_volume(day(5)), 350);
+            assert_eq!(stats.rolling_volume(day(29)), 350);
+            assert_eq!(stats.rolling_volume(day(30)), 50);
+            assert_eq!(stats.rolling_volume(day(35)), 0);
+        }
+
+        #[test]
+        fn reused_buckets_start_empty() {
+            let mut stats = trader_stats();
+            stats.record_volume(100, day(0));
+            stats.record_volume(7, day(VOLUME_WINDOW_DAYS as i64));
+            assert_eq!(stats.rolling_volume(day(VOLUME_WINDOW_DAYS as i64)), 7);
+
+            stats.record_volume(9, day(100));
+            assert_eq!(stats.rolling_volume(day(100)), 9);
+            assert_eq!(stats.daily_volume.iter().sum::<u64>(), 9);
+        }
+
+        #[test]
+        fn largest_qualifying_tier_discounts_the_fee() {
+            let mut torrent = Torrent::try_from_slice(&[0; Torrent::SIZE]).unwrap();
+            torrent.volume_tiers[0] = VolumeTier {
+                min_volume: 1_000,
+                discount_bps: 1_000,
+            };
+            torrent.volume_tiers[1] = VolumeTier {
+                min_volume: 10_000,
+                discount_bps: 2_500,
+            };
+
+            assert_eq!(torrent.discounted_fee_bps(30, 999), 30);
+            assert_eq!(torrent.discounted_fee_bps(30, 1_000), 27);
+            assert_eq!(torrent.discounted_fee_bps(30, 20_000), 22);
+        }
+    }
+
+    mod dynamic_fee {
+        use super::*;
+
+        fn pool(enabled: bool) -> Pool {
+            let mut pool = Pool::try_from_slice(&[0; Pool::SIZE]).unwrap();
+            pool.fee_bps = 30;
+            pool.dynamic_fee = DynamicFeeConfig {
+                enabled,
+                min_fee_bps: 10,
+                max_fee_bps: 100,
+                variable_fee_control: 5_000,
+                filter_period: 10,
+                decay_period: 100,
+                reduction_factor: 5_000,
+            };
+            pool.volatility_accumulator = 100;
+            pool.last_volatility_slot = 1_000;
+            pool
+        }
+
+        #[test]
+        fn static_fee_applies_when_disabled() {
+            let mut pool = pool(false);
+            assert_eq!(pool.current_fee_bps(1_005), 30);
+
+            pool.record_price_move(1_000, 1_000, 2_000, 500, 1_005);
+            assert_eq!(pool.volatility_accumulator, 100);
+            assert_eq!(pool.last_volatility_slot, 1_000);
+        }
+
+        #[test]
+        fn volatility_is_kept_then_reduced_then_dropped() {
+            let pool = pool(true);
+            assert_eq!(pool.volatility_at(1_000), 100);
+            assert_eq!(pool.volatility_at(1_009), 100);
+            assert_eq!(pool.volatility_at(1_010), 50);
+            assert_eq!(pool.volatility_at(1_099), 50);
+            assert_eq!(pool.volatility_at(1_100), 0);
+        }
+
+        #[test]
+        fn fee_follows_volatility_within_bounds() {
+            let mut pool = pool(true);
+            assert_eq!(pool.current_fee_bps(1_005), 60);
+            assert_eq!(pool.current_fee_bps(1_050), 35);
+            assert_eq!(pool.current_fee_bps(1_200), 10);
+
+            pool.volatility_accumulator = 1_000;
+            assert_eq!(pool.current_fee_bps(1_005), 100);
+        }
+
+        #[test]
+        fn empty_reserves_record_no_price_move() {
+            let mut pool = pool(true);
+
+            pool.record_price_move(1_000, 0, 1_100, 0, 1_050);
+            pool.record_price_move(0, 1_000, 0, 909, 1_050);
+            assert_eq!(pool.volatility_accumulator, 100);
+            assert_eq!(pool.last_volatility_slot, 1_000);
+        }
+
+        #[test]
+        fn price_moves_add_to_decayed_volatility() {
+            let mut pool = pool(true);
+
+            // Input price falls from 1 to 909/1100, a 17.36% move
+            pool.record_price_move(1_000, 1_000, 1_100, 909, 1_050);
+            assert_eq!(pool.volatility_accumulator, 50 + 1_736);
+            assert_eq!(pool.last_volatility_slot, 1_050);
+
+            pool.volatility_accumulator = MAX_VOLATILITY_ACCUMULATOR - 1;
+            pool.record_price_move(1_000, 1_000, 1_100, 909, 1_051);
+            assert_eq!(pool.volatility_accumulator, MAX_VOLATILITY_ACCUMULATOR);
+        }
+    }
+
+    mod native_wallet {
+        use super::*;
+        use anchor_spl::token::spl_token;
+
+        fn program(key: Pubkey) -> AccountInfo<'static> {
+            let mut info = account(key, Pubkey::default(), vec![], false);
+            info.executable = true;
+            info
+        }
+
+        struct Accounts {
+            user: Signer<'static>,
+            token_program: Interface<'static, TokenInterface>,
+            system_program: Program<'static, System>,
+            native_wallet: UncheckedAccount<'static>,
+        }
+
+        fn accounts(native_wallet_owner: Pubkey) -> Accounts {
+            Accounts {
+                user: Signer::try_from(&account(Pubkey::new_unique(), System::id(), vec![], true))
+                    .unwrap(),
+                token_program: Interface::try_from(&program(spl_token::ID)).unwrap(),
+                system_program: Program::try_from(&program(System::id())).unwrap(),
+                native_wallet: UncheckedAccount::try_from(account(
+                    Pubkey::new_unique(),
+                    native_wallet_owner,
+                    vec![],
+                    false,
+                )),
+            }
+        }
+
+        fn mint(key: Pubkey) -> InterfaceAccount<'static, token_interface::Mint> {
+            let mut data = vec![0; spl_token::state::Mint::LEN];
+            spl_token::state::Mint {
+                decimals: 9,
+                is_initialized: true,
+                ..Default::default()
+            }
+            .pack_into_slice(&mut data);
+            InterfaceAccount::try_from(&account(key, spl_token::ID, data, false)).unwrap()
+        }
+
+        fn wallet(
+            owner: Pubkey,
+            mint: Pubkey,
+            amount: u64,
+        ) -> Box<InterfaceAccount<'static, token_interface::TokenAccount>> {
+            let mut data = vec![0; spl_token::state::Account::LEN];
+            spl_token::state::Account {
+                mint,
+                owner,
+                amount,
+                state: spl_token::state::AccountState::Initialized,
+                ..Default::default()
+            }
+            .pack_into_slice(&mut data);
+            Box::new(
+                InterfaceAccount::try_from(&account(
+                    Pubkey::new_unique(),
+                    spl_token::ID,
+                    data,
+                    false,
+                ))
+                .unwrap(),
+            )
+        }
+
+        fn native_wallet(accounts: &Accounts) -> Option<NativeWallet<'_, 'static>> {
+            Some(NativeWallet {
+                account: &accounts.native_wallet,
+                pool: Pubkey::new_unique(),
+                bump: 255,
+                system_program: &accounts.system_program,
+            })
+        }
+
+        #[test]
+        fn passed_wallets_are_used_and_left_open() {
+            let accounts = accounts(System::id());
+            let mint = mint(spl_token::native_mint::ID);
+            let passed = wallet(accounts.user.key(), mint.key(), 500);
+
+            let wallet = open_user_wallet(
+                &Some(passed.clone()),
+                &native_wallet(&accounts),
+                &accounts.user,
+                &mint,
+                &accounts.token_program,
+                100,
+            )
+            .unwrap();
+            assert_eq!(wallet.account.key(), passed.key());
+            assert_eq!(wallet.amount, 500);
+            assert!(!wallet.temporary);
+
+            // Closing is a no-op for the user's own wallets, so no CPI is made
+            close_user_wallet(&wallet, &accounts.user, &accounts.token_program).unwrap();
+        }
+
+        #[test]
+        fn only_native_sides_can_leave_out_the_wallet() {
+            let accounts = accounts(System::id());
+
+            let result = open_user_wallet(
+                &None,
+                &native_wallet(&accounts),
+                &accounts.user,
+                &mint(Pubkey::new_unique()),
+                &accounts.token_program,
+                100,
+            );
+            assert_eq!(result.err().unwrap(), CustomError::MissingUserWallet.into());
+
+            let result = open_user_wallet(
+                &None,
+                &None,
+                &accounts.user,
+                &mint(spl_token::native_mint::ID),
+                &accounts.token_program,
+                100,
+            );
+            assert_eq!(result.err().unwrap(), CustomError::MissingUserWallet.into());
+        }
+
+        #[test]
+        fn native_wallet_pays_one_side_at_a_time() {
+            // Already initialised as a token account for the other side
+            let accounts = accounts(spl_token::ID);
+
+            let result = open_user_wallet(
+                &None,
+                &native_wallet(&accounts),
+                &accounts.user,
+                &mint(spl_token::native_mint::ID),
+                &accounts.token_program,
+                100,
+            );
+            assert_eq!(result.err().unwrap(), CustomError::NativeWalletInUse.into());
+        }
+    }
 }
@@ -0,0 +1,95 @@
+//! Seeds and derivation of every account the program owns or signs for.
+//!
+//! Pools have no seed prefix. Their address is derived from the torrent, the pair's mints
+//! and the little-endian fee tier.
+
+use anchor_lang::prelude::Pubkey;
+
+pub const TORRENT_SEED: &[u8] = b"torrent";
+pub const LIQUIDITY_TOKEN_SEED: &[u8] = b"token";
+pub const X_VAULT_SEED: &[u8] = b"x_vault";
+pub const Y_VAULT_SEED: &[u8] = b"y_vault";
+pub const POSITION_SEED: &[u8] = b"position";
+pub const PENDING_CHANGE_SEED: &[u8] = b"change";
+pub const ALLOWANCE_SEED: &[u8] = b"allowance";
+pub const REFERRER_SEED: &[u8] = b"referrer";
+pub const TRADER_STATS_SEED: &[u8] = b"trader_stats";
+pub const FARM_SEED: &[u8] = b"farm";
+pub const FARM_LP_VAULT_SEED: &[u8] = b"farm_lp_vault";
+pub const FARM_REWARD_VAULT_SEED: &[u8] = b"farm_reward_vault";
+pub const STAKE_SEED: &[u8] = b"stake";
+pub const NATIVE_WALLET_SEED: &[u8] = b"native_wallet";
+
+fn find(seeds: &[&[u8]]) -> (Pubkey, u8) {
+    Pubkey::find_program_address(seeds, &crate::ID)
+}
+
+pub fn find_torrent_address(authority: &Pubkey) -> (Pubkey, u8) {
+    find(&[TORRENT_SEED, authority.as_ref()])
+}
+
+pub fn find_liquidity_token_mint_address(torrent: &Pubkey) -> (Pubkey, u8) {
+    find(&[LIQUIDITY_TOKEN_SEED, torrent.as_ref()])
+}
+
+pub fn find_pool_address(
+    torrent: &Pubkey,
+    mint_x: &Pubkey,
+    mint_y: &Pubkey,
+    fee_tier: u16,
+) -> (Pubkey, u8) {
+    find(&[
+        torrent.as_ref(),
+        mint_x.as_ref(),
+        mint_y.as_ref(),
+        &fee_tier.to_le_bytes(),
+    ])
+}
+
+pub fn find_x_vault_address(pool: &Pubkey) -> (Pubkey, u8) {
+    find(&[X_VAULT_SEED, pool.as_ref()])
+}
+
+pub fn find_y_vault_address(pool: &Pubkey) -> (Pubkey, u8) {
+    find(&[Y_VAULT_SEED, pool.as_ref()])
+}
+
+pub fn find_position_address(position_mint: &Pubkey) -> (Pubkey, u8) {
+    find(&[POSITION_SEED, position_mint.as_ref()])
+}
+
+pub fn find_pending_change_address(torrent: &Pubkey, id: u64) -> (Pubkey, u8) {
+    find(&[PENDING_CHANGE_SEED, torrent.as_ref(), &id.to_le_bytes()])
+}
+
+pub fn find_allowance_address(torrent: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+    find(&[ALLOWANCE_SEED, torrent.as_ref(), user.as_ref()])
+}
+
+pub fn find_referrer_address(torrent: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+    find(&[REFERRER_SEED, torrent.as_ref(), owner.as_ref()])
+}
+
+pub fn find_trader_stats_address(pool: &Pubkey, trader: &Pubkey) -> (Pubkey, u8) {
+    find(&[TRADER_STATS_SEED, pool.as_ref(), trader.as_ref()])
+}
+
+pub fn find_farm_address(torrent: &Pubkey, id: u64) -> (Pubkey, u8) {
+    find(&[FARM_SEED, torrent.as_ref(), &id.to_le_bytes()])
+}
+
+pub fn find_farm_lp_vault_address(farm: &Pubkey) -> (Pubkey, u8) {
+    find(&[FARM_LP_VAULT_SEED, farm.as_ref()])
+}
+
+pub fn find_farm_reward_vault_address(farm: &Pubkey, reward_mint: &Pubkey) -> (Pubkey, u8) {
+    find(&[FARM_REWARD_VAULT_SEED, farm.as_ref(), reward_mint.as_ref()])
+}
+
+pub fn find_stake_account_address(farm: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+    find(&[STAKE_SEED, farm.as_ref(), user.as_ref()])
+}
+
+pub fn find_native_wallet_address(pool: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+    find(&[NATIVE_WALLET_SEED, pool.as_ref(), user.as_ref()])
+}
@@ -0,0 +1,32 @@
+import * as anchor from '@project-serum/anchor';
+
+// Seeds from the program's `pda` module
+export const TORRENT_SEED = Buffer.from("torrent");
+export const LIQUIDITY_TOKEN_SEED = Buffer.from("token");
+export const X_VAULT_SEED = Buffer.from("x_vault");
+export const Y_VAULT_SEED = Buffer.from("y_vault");
+
+type Address = Promise<[anchor.web3.PublicKey, number]>;
+
+export const findTorrentAddress = (authority: anchor.web3.PublicKey, programId: anchor.web3.PublicKey)
+: Address => anchor.web3.PublicKey.findProgramAddress([TORRENT_SEED, authority.toBuffer()], programId);
+
+export const findLiquidityTokenMintAddress = (torrent: anchor.web3.PublicKey, programId: anchor.web3.PublicKey)
+: Address => anchor.web3.PublicKey.findProgramAddress([LIQUIDITY_TOKEN_SEED, torrent.toBuffer()], programId);
+
+// Pools have no seed prefix
+export const findPoolAddress = (torrent: anchor.web3.PublicKey, mintX: anchor.web3.PublicKey,
+  mintY: anchor.web3.PublicKey, feeTier: number, programId: anchor.web3.PublicKey): Address => {
+    let feeTierSeed = Buffer.alloc(2);
+    feeTierSeed.writeUInt16LE(feeTier);
+
+    return anchor.web3.PublicKey.findProgramAddress(
+      [torrent.toBuffer(), mintX.toBuffer(), mintY.toBuffer(), feeTierSeed], programId
+    );
+}
+
+export const findXVaultAddress = (pool: anchor.web3.PublicKey, programId: anchor.web3.PublicKey)
+: Address => anchor.web3.PublicKey.findProgramAddress([X_VAULT_SEED, pool.toBuffer()], programId);
+
+export const findYVaultAddress = (pool: anchor.web3.PublicKey, programId: anchor.web3.PublicKey)
+: Address => anchor.web3.PublicKey.findProgramAddress([Y_VAULT_SEED, pool.toBuffer()], programId);
@@ -10,6 +10,13 @@ import {
   mintTokensToWallet,
   customGetTokenAccountBalance
 } from "./utils";
+import {
+  findTorrentAddress,
+  findLiquidityTokenMintAddress,
+  findPoolAddress,
+  findXVaultAddress,
+  findYVaultAddress
+} from "./pda";
 import { TokenError } from "@solana/spl-token";
 
 describe("Torrent", () => {
@@ -38,14 +45,8 @@ describe("Torrent", () => {
     // Airdrop sol to authority
     await airdrop(provider.connection, authority.publicKey, 1);
 
-    [torrentPDA, torrentBump] = await anchor.web3.PublicKey.findProgramAddress(
-      [Buffer.from(anchor.utils.bytes.utf8.encode("torrent")), authority.publicKey
-      .toBuffer()], program.programId
-    );
-
-    [liquidityTokenMint, ltBump] = await anchor.web3.PublicKey.findProgramAddress([
-      Buffer.from(anchor.utils.bytes.utf8.encode("token")), torrentPDA.toBuffer()
-    ], program.programId);
+    [torrentPDA, torrentBump] = await findTorrentAddress(authority.publicKey, program.programId);
+    [liquidityTokenMint, ltBump] = await findLiquidityTokenMintAddress(torrentPDA, program.programId);
     
     let ltDecimals = 0;
     await program.methods
@@ -83,33 +84,34 @@ describe("Torrent", () => {
     await mintTokensToWallet(provider.connection, authorityYWallet, initialY + 2, mintAuthority, 
       yTokenMint, mintAuthority);
 
-    [xyPool, xyPoolBump] = await anchor.web3.PublicKey.findProgramAddress([
-      torrentPDA.toBuffer(), xTokenMint.toBuffer(), yTokenMint.toBuffer()
-    ], program.programId);
-
-    [xVault, xVaultBump] = await anchor.web3.PublicKey.findProgramAddress([
-      Buffer.from(anchor.utils.bytes.utf8.encode("x_vault")), xyPool.toBuffer()
-    ], program.programId);
-
-    [yVault, yVaultBump] = await anchor.web3.PublicKey.findProgramAddress([
-      Buffer.from(anchor.utils.bytes.utf8.encode("y_vault")), xyPool.toBuffer()
-    ], program.programId);
+    let feeTier = 30;
+    [xyPool, xyPoolBump] = await findPoolAddress(torrentPDA, xTokenMint, yTokenMint, feeTier,
+      program.programId);
+    [xVault, xVaultBump] = await findXVaultAddress(xyPool, program.programId);
+    [yVault, yVaultBump] = await findYVaultAddress(xyPool, program.programId);
 
     try {
     await program.methods
-      .initializePool(new anchor.BN(initialX), new anchor.BN(initialY))
+      .initializePool(new anchor.BN(initialX), new anchor.BN(initialY), feeTier)
       .accounts({
         torrent: torrentPDA,
         liquidityTokenMint: liquidityTokenMint,
-        authority: authority.publicKey,
+        creator: authority.publicKey,
         mintX: xTokenMint,
         mintY: yTokenMint,
-        authorityXWallet: authorityXWallet,
-        authorityYWallet: authorityYWallet,
-        authorityLiquidityTokenWallet: authorityLtWallet,
+        creatorXWallet: authorityXWallet,
+        creatorYWallet: authorityYWallet,
+        creatorLiquidityTokenWallet: authorityLtWallet,
         pool: xyPool,
         xTokenVault: xVault,
         yTokenVault: yVault,
+        treasury: authority.publicKey,
+        feeMint: null,
+        creatorFeeWallet: null,
+        treasuryFeeWallet: null,
+        feeTokenProgram: null,
+        tokenXProgram: spl.TOKEN_PROGRAM_ID,
+        tokenYProgram: spl.TOKEN_PROGRAM_ID,
       })
       .signers([authority])
       .rpc();
@@ -158,17 +160,23 @@ it ("Simulates adding liquidity", async () => {
     
 
     await program.methods
-      .addLiquidity(new anchor.BN(amountX), new anchor.BN(amountY))
+      .addLiquidity(new anchor.BN(amountX), new anchor.BN(amountY), null)
       .accounts({
         user: liquidityProvider.publicKey,
         torrent: torrentPDA,
         pool: xyPool,
         xTokenVault: xVault,
         yTokenVault: yVault,
+        mintX: xTokenMint,
+        mintY: yTokenMint,
         liquidityTokenMint: liquidityTokenMint,
         userXWallet: xTokenATA,
         userYWallet: yTokenATA,
+        nativeWallet: null,
         userLiquidityTokenWallet: liquidityTokenATA,
+        allowance: null,
+        tokenXProgram: spl.TOKEN_PROGRAM_ID,
+        tokenYProgram: spl.TOKEN_PROGRAM_ID,
       })
       .signers([liquidityProvider]);
 